@@ -1,4 +1,4 @@
-use genai::chat::ChatMessage;
+use genai::chat::{ChatMessage, ContentPart, ImageSource, ToolCall, ToolResponse};
 use genai::Client;
 use serde::{Deserialize, Serialize};
 use std::env;
@@ -54,6 +54,53 @@ pub struct ChatRequestPayload {
     pub previous_xml: Option<String>,
     pub session_id: Option<String>,
     pub access_code: Option<String>,
+    // 原样透传给 provider 请求体的逃生舱——调用方可以设置 temperature、top_p、
+    // response_format 等字段而不需要我们为每个 provider 单独写代码；
+    // messages/tools/stream 始终由我们注入，不会被覆盖
+    pub request_overrides: Option<serde_json::Value>,
+}
+
+// messages/tools/stream 始终由我们自己构建，request_overrides 不允许覆盖它们：
+// 空 messages/tools 会让对话/工具凭空消失，stream=false 会打破 SSE 解析
+const PROTECTED_OVERRIDE_KEYS: [&str; 3] = ["messages", "tools", "stream"];
+
+// 把 request_overrides 合并进 provider 请求体，但保留 messages/tools/stream
+// 不受影响——合并后把这几个 key 的原值重新写回去
+pub fn merge_request_overrides(base: &mut serde_json::Value, overrides: &serde_json::Value) {
+    let protected: Vec<(String, serde_json::Value)> = PROTECTED_OVERRIDE_KEYS
+        .iter()
+        .filter_map(|key| base.get(*key).map(|value| (key.to_string(), value.clone())))
+        .collect();
+
+    merge_json(base, overrides);
+
+    if let serde_json::Value::Object(base_map) = base {
+        for (key, value) in protected {
+            base_map.insert(key, value);
+        }
+    }
+}
+
+// 把 overrides 递归合并进 base，调用方（overrides）的值优先；
+// 两边都是 object 时逐 key 合并，否则直接用 overrides 的值整体替换；
+// overrides 为 null 时跳过，不会把 base 里已有的值清成 null
+fn merge_json(base: &mut serde_json::Value, overrides: &serde_json::Value) {
+    if overrides.is_null() {
+        return;
+    }
+    match (base, overrides) {
+        (serde_json::Value::Object(base_map), serde_json::Value::Object(override_map)) => {
+            for (key, override_value) in override_map {
+                merge_json(
+                    base_map.entry(key.clone()).or_insert(serde_json::Value::Null),
+                    override_value,
+                );
+            }
+        }
+        (base_slot, override_value) => {
+            *base_slot = override_value.clone();
+        }
+    }
 }
 
 // UI 消息结构（兼容前端格式）
@@ -87,6 +134,7 @@ pub enum MessagePart {
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum StreamEvent {
     Start,
+    StepStart { step: u32 },
     TextDelta { delta: String },
     ToolCallStart { tool_call_id: String, tool_name: String },
     ToolInputDelta { tool_call_id: String, delta: String },
@@ -95,19 +143,52 @@ pub enum StreamEvent {
     Error { error: String },
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct UsageStats {
     pub input_tokens: u32,
     pub output_tokens: u32,
     pub cached_input_tokens: Option<u32>,
 }
 
+impl UsageStats {
+    // 累加多个 agent 步骤的 usage，cached_input_tokens 取最后一次出现的值
+    pub fn accumulate(&mut self, other: &UsageStats) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        if other.cached_input_tokens.is_some() {
+            self.cached_input_tokens = other.cached_input_tokens;
+        }
+    }
+}
+
+// 认证方式：常规的 provider API key，或是网关前置的 access token
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMode {
+    ApiKey,
+    AccessToken,
+}
+
+impl AuthMode {
+    pub fn from_string(s: &str) -> Result<Self, String> {
+        match s.to_lowercase().as_str() {
+            "api_key" | "apikey" => Ok(Self::ApiKey),
+            "access_token" | "accesstoken" | "token" => Ok(Self::AccessToken),
+            _ => Err(format!("Unknown auth mode: {}", s)),
+        }
+    }
+}
+
 // AI 配置
 pub struct AIConfig {
     pub provider: AIProvider,
     pub model_id: String,
     pub api_key: Option<String>,
     pub base_url: Option<String>,
+    pub auth_mode: AuthMode,
+    pub access_token: Option<String>,
+    pub auth_header_name: String,
+    pub auth_scheme: String,
 }
 
 impl AIConfig {
@@ -116,6 +197,29 @@ impl AIConfig {
         model_override: Option<String>,
         api_key_override: Option<String>,
         base_url_override: Option<String>,
+    ) -> Result<Self, String> {
+        Self::from_env_and_overrides_with_auth(
+            provider_override,
+            model_override,
+            api_key_override,
+            base_url_override,
+            None,
+            None,
+            None,
+            None,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_env_and_overrides_with_auth(
+        provider_override: Option<String>,
+        model_override: Option<String>,
+        api_key_override: Option<String>,
+        base_url_override: Option<String>,
+        auth_mode_override: Option<String>,
+        access_token_override: Option<String>,
+        auth_header_name_override: Option<String>,
+        auth_scheme_override: Option<String>,
     ) -> Result<Self, String> {
         // 优先使用 override，否则使用环境变量
         let provider_str = provider_override
@@ -149,37 +253,214 @@ impl AIConfig {
             _ => None,
         });
 
+        let auth_mode = auth_mode_override
+            .or_else(|| env::var("AUTH_MODE").ok())
+            .map(|s| AuthMode::from_string(&s))
+            .transpose()?
+            .unwrap_or(AuthMode::ApiKey);
+
+        let access_token = access_token_override.or_else(|| env::var("ACCESS_TOKEN").ok());
+
+        let auth_header_name = auth_header_name_override
+            .or_else(|| env::var("AUTH_HEADER_NAME").ok())
+            .unwrap_or_else(|| "Authorization".to_string());
+
+        let auth_scheme = auth_scheme_override
+            .or_else(|| env::var("AUTH_SCHEME").ok())
+            .unwrap_or_else(|| "Bearer".to_string());
+
         Ok(Self {
             provider,
             model_id,
             api_key,
             base_url,
+            auth_mode,
+            access_token,
+            auth_header_name,
+            auth_scheme,
         })
     }
+
+    // 按配置的认证方式解析出请求要携带的 (header 名, header 值)
+    pub fn resolve_auth_header(&self) -> Result<(String, String), String> {
+        let token = match self.auth_mode {
+            AuthMode::ApiKey => self
+                .api_key
+                .clone()
+                .ok_or_else(|| format!("{:?} API key not configured", self.provider))?,
+            AuthMode::AccessToken => self
+                .access_token
+                .clone()
+                .ok_or_else(|| "Access token not configured (ACCESS_TOKEN)".to_string())?,
+        };
+
+        let value = if self.auth_scheme.is_empty() {
+            token
+        } else {
+            format!("{} {}", self.auth_scheme, token)
+        };
+
+        Ok((self.auth_header_name.clone(), value))
+    }
 }
 
-// 转换 UI 消息到 genai 消息格式
+// 这些 provider 的模型普遍支持图片输入；其余 provider（Groq/DeepSeek/Ollama/Cohere
+// 等）的主流模型大多是纯文本，把 File part 硬塞进请求会被 API 直接拒绝
+//
+// ai_commands.rs 里手写 HTTP 的几个 agent loop 也靠这个判断要不要把 File part
+// 转成图片 content block，所以是 pub(crate) 而不是只给本文件用
+pub(crate) fn provider_supports_images(provider: &AIProvider) -> bool {
+    matches!(
+        provider,
+        AIProvider::OpenAI | AIProvider::Anthropic | AIProvider::Gemini | AIProvider::Bedrock
+    )
+}
+
+// 不支持图片输入的 provider 统一用这句话代替附件，而不是静默丢弃
+fn file_omitted_message(provider: &AIProvider, url: &str) -> String {
+    format!(
+        "[Attached file omitted: {:?} does not support image input ({})]",
+        provider, url
+    )
+}
+
+// 把 `data:<mime>;base64,<data>` 形式的 data URL 拆成 (mime, base64 data)；
+// 不是 base64 编码的 data URL（例如 data:text/plain,...）不支持，返回 None
+pub(crate) fn parse_base64_data_url(url: &str) -> Option<(String, String)> {
+    let rest = url.strip_prefix("data:")?;
+    let (meta, data) = rest.split_once(',')?;
+    if !meta.split(';').any(|segment| segment == "base64") {
+        return None;
+    }
+    let mime = meta.split(';').next().filter(|s| !s.is_empty()).unwrap_or("application/octet-stream");
+    Some((mime.to_string(), data.to_string()))
+}
+
+// 把一个 File part 变成 genai 的图片 ContentPart；拿到不支持图片的 provider 时
+// 退化成一句文字提示，而不是让请求直接被 API 拒绝
+fn file_part_to_content(
+    url: &str,
+    media_type: Option<&str>,
+    provider: &AIProvider,
+) -> ContentPart {
+    if !provider_supports_images(provider) {
+        return ContentPart::Text(file_omitted_message(provider, url));
+    }
+
+    if let Some((mime, data)) = parse_base64_data_url(url) {
+        let content_type = media_type.map(str::to_string).unwrap_or(mime);
+        ContentPart::Image {
+            content_type,
+            source: ImageSource::Base64(data.into()),
+        }
+    } else {
+        // 远程 URL 原样传给 provider，由对方负责拉取
+        ContentPart::Image {
+            content_type: media_type.unwrap_or("image/*").to_string(),
+            source: ImageSource::Url(url.to_string()),
+        }
+    }
+}
+
+// 把一个 File part 变成 OpenAI chat/completions 的 image_url content block；
+// image_url.url 可以直接吃 data URL 或远程 URL，不用像 Anthropic 那样拆成
+// media_type + base64 两个字段。调用方已经用 provider_supports_images 判断过
+// 要不要走这条路，这里只管转换
+pub(crate) fn file_part_to_openai_content(url: &str) -> serde_json::Value {
+    serde_json::json!({"type": "image_url", "image_url": {"url": url}})
+}
+
+// 不支持图片输入的 provider 用这句话代替附件；不专属某个 provider 枚举值，
+// 因为调用方（ai_commands.rs 的 OpenAI 兼容、Anthropic、Cohere 几条 loop）覆盖好几个具体 provider
+pub(crate) fn image_omitted_message(url: &str) -> String {
+    format!("[Attached file omitted: this provider does not support image input ({})]", url)
+}
+
+// 把一个 File part 变成 Anthropic messages API 的 image content block；
+// Anthropic 的 image source 只认 base64（media_type + data），远程 URL 降级
+// 成文字提示而不是硬塞一个它不支持的字段
+pub(crate) fn file_part_to_anthropic_content(
+    url: &str,
+    media_type: Option<&str>,
+) -> serde_json::Value {
+    if let Some((mime, data)) = parse_base64_data_url(url) {
+        let media_type = media_type.map(str::to_string).unwrap_or(mime);
+        serde_json::json!({
+            "type": "image",
+            "source": {"type": "base64", "media_type": media_type, "data": data}
+        })
+    } else {
+        serde_json::json!({
+            "type": "text",
+            "text": format!("[Attached file omitted: Anthropic image blocks require base64 data, not a remote URL ({})]", url)
+        })
+    }
+}
+
+// 转换 UI 消息到 genai 消息格式——文本和图片拼成 content parts；assistant 发起的
+// 工具调用变成 ToolCalls 消息，随后的工具执行结果变成一条 ToolResponses 消息，
+// 这样 provider 看到的是一段连贯的、带工具调用历史的对话
 pub fn convert_ui_messages_to_genai(
     ui_messages: Vec<UIMessage>,
+    provider: &AIProvider,
 ) -> Result<Vec<ChatMessage>, String> {
     let mut messages = Vec::new();
 
     for ui_msg in ui_messages {
-        // 提取文本内容
-        let mut text_parts = Vec::new();
-        for part in &ui_msg.parts {
-            match part {
-                MessagePart::Text { text } => text_parts.push(text.clone()),
-                _ => {} // 暂时忽略其他类型
-            }
+        let tool_calls: Vec<ToolCall> = ui_msg
+            .parts
+            .iter()
+            .filter_map(|part| match part {
+                MessagePart::ToolCall { tool_call_id, tool_name, input } => Some(ToolCall {
+                    call_id: tool_call_id.clone(),
+                    fn_name: tool_name.clone(),
+                    fn_arguments: input.clone(),
+                }),
+                _ => None,
+            })
+            .collect();
+
+        let tool_responses: Vec<ToolResponse> = ui_msg
+            .parts
+            .iter()
+            .filter_map(|part| match part {
+                MessagePart::ToolResult { tool_call_id, result, .. } => Some(ToolResponse {
+                    call_id: tool_call_id.clone(),
+                    content: serde_json::to_string(result).unwrap_or_default(),
+                }),
+                _ => None,
+            })
+            .collect();
+
+        // 一条消息里 ToolResult 先于其他内容判断：它代表的是工具执行完毕后喂回
+        // 模型的结果，不再携带普通文本/图片内容
+        if !tool_responses.is_empty() {
+            messages.push(ChatMessage::from(tool_responses));
+            continue;
+        }
+
+        // assistant 发起的工具调用同理自成一条消息
+        if !tool_calls.is_empty() {
+            messages.push(ChatMessage::from(tool_calls));
+            continue;
         }
 
-        let content = text_parts.join("\n");
+        let content_parts: Vec<ContentPart> = ui_msg
+            .parts
+            .iter()
+            .filter_map(|part| match part {
+                MessagePart::Text { text } => Some(ContentPart::Text(text.clone())),
+                MessagePart::File { url, media_type } => {
+                    Some(file_part_to_content(url, media_type.as_deref(), provider))
+                }
+                _ => None,
+            })
+            .collect();
 
         let message = match ui_msg.role.as_str() {
-            "user" => ChatMessage::user(content),
-            "assistant" => ChatMessage::assistant(content),
-            "system" => ChatMessage::system(content),
+            "user" => ChatMessage::user(content_parts),
+            "assistant" => ChatMessage::assistant(content_parts),
+            "system" => ChatMessage::system(content_parts),
             _ => return Err(format!("Unknown role: {}", ui_msg.role)),
         };
 
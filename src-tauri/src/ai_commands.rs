@@ -1,8 +1,31 @@
-use crate::ai_chat::{get_system_prompt, AIConfig, ChatRequestPayload, StreamEvent, UsageStats};
+use crate::ai_chat::{
+    convert_ui_messages_to_genai, create_client, file_part_to_anthropic_content,
+    file_part_to_openai_content, get_system_prompt, image_omitted_message,
+    merge_request_overrides, provider_supports_images, AIConfig, AIProvider, ChatRequestPayload,
+    MessagePart, StreamEvent, UIMessage, UsageStats,
+};
+use crate::rate_limit::RateLimiter;
 use futures_util::StreamExt;
+use genai::chat::{ChatMessage, ChatRequest, ChatStreamEvent};
 use reqwest::Client;
 use serde_json::json;
-use tauri::{Emitter, Window};
+use tauri::ipc::Channel;
+use tauri::{Emitter, Manager, Window};
+
+// agent 循环允许的最大步数，防止模型反复调用工具导致死循环
+const MAX_AGENT_STEPS: u32 = 8;
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+// 把过长的错误片段截断到可读长度，避免把一整段截断的 XML 丢进错误事件里
+fn truncate_for_error(s: &str) -> String {
+    const MAX_CHARS: usize = 200;
+    if s.chars().count() > MAX_CHARS {
+        format!("{}...", s.chars().take(MAX_CHARS).collect::<String>())
+    } else {
+        s.to_string()
+    }
+}
 
 // 创建工具定义（OpenAI格式）
 fn create_tools() -> Vec<serde_json::Value> {
@@ -75,7 +98,58 @@ fn create_tools() -> Vec<serde_json::Value> {
     ]
 }
 
+// 创建工具定义（Anthropic格式）—— name/description/input_schema 是扁平的，
+// 不像 OpenAI 那样套一层 "function"
+fn create_anthropic_tools() -> Vec<serde_json::Value> {
+    create_tools()
+        .into_iter()
+        .map(|tool| {
+            let function = &tool["function"];
+            json!({
+                "name": function["name"],
+                "description": function["description"],
+                "input_schema": function["parameters"],
+            })
+        })
+        .collect()
+}
+
+// 创建工具定义（Cohere格式）—— 用 parameter_definitions（逐参数的
+// {description, type, required}）取代 JSON Schema
+fn create_cohere_tools() -> Vec<serde_json::Value> {
+    create_tools()
+        .into_iter()
+        .map(|tool| {
+            let function = &tool["function"];
+            let properties = function["parameters"]["properties"].as_object().cloned().unwrap_or_default();
+            let required: Vec<String> = function["parameters"]["required"]
+                .as_array()
+                .map(|arr| arr.iter().filter_map(|v| v.as_str().map(String::from)).collect())
+                .unwrap_or_default();
+
+            let parameter_definitions: serde_json::Map<String, serde_json::Value> = properties
+                .into_iter()
+                .map(|(name, schema)| {
+                    let def = json!({
+                        "description": schema.get("description").cloned().unwrap_or(serde_json::Value::String(String::new())),
+                        "type": schema.get("type").cloned().unwrap_or(serde_json::Value::String("string".to_string())),
+                        "required": required.contains(&name),
+                    });
+                    (name, def)
+                })
+                .collect();
+
+            json!({
+                "name": function["name"],
+                "description": function["description"],
+                "parameter_definitions": parameter_definitions,
+            })
+        })
+        .collect()
+}
+
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn chat_stream(
     window: Window,
     payload: String,
@@ -84,11 +158,33 @@ pub async fn chat_stream(
     api_key_override: Option<String>,
     base_url_override: Option<String>,
     minimal_style: Option<bool>,
+    auth_mode: Option<String>,
+    access_token_override: Option<String>,
+    auth_header_name_override: Option<String>,
+    auth_scheme_override: Option<String>,
+    invoke_token: Option<String>,
 ) -> Result<(), String> {
     // 解析请求
     let request: ChatRequestPayload =
         serde_json::from_str(&payload).map_err(|e| format!("Invalid request: {}", e))?;
 
+    // 这个命令直接消耗 AI provider 额度，要求带上 verify_access_code 为这个
+    // session 签发的 invoke token，防止 WebView 里加载到的远程/第三方内容
+    // 绕过校验调用它
+    let session_id = request.session_id.clone().unwrap_or_default();
+    window
+        .state::<crate::invoke_auth::InvokeAuthGate>()
+        .verify(&session_id, invoke_token.as_deref())
+        .map_err(|e| e.to_string())?;
+
+    // 限流的计费单位：优先用 access code，没有的话退回 session_id
+    let rate_limit_key = request
+        .access_code
+        .clone()
+        .filter(|code| !code.is_empty())
+        .or_else(|| request.session_id.clone())
+        .unwrap_or_else(|| "anonymous".to_string());
+
     // 验证 access code
     if let Ok(access_codes_str) = std::env::var("ACCESS_CODE_LIST") {
         let access_codes: Vec<String> = access_codes_str
@@ -98,31 +194,51 @@ pub async fn chat_stream(
             .collect();
 
         if !access_codes.is_empty() {
-            let provided_code = request.access_code.unwrap_or_default();
+            let provided_code = request.access_code.clone().unwrap_or_default();
             if !access_codes.contains(&provided_code) {
                 return Err("Invalid or missing access code".to_string());
             }
         }
     }
 
-    // 获取配置
-    let config = AIConfig::from_env_and_overrides(
-        provider_override,
-        model_override,
-        api_key_override,
-        base_url_override,
+    // get_config 里宣传的 daily/TPM 限额，在真正发请求之前先占一个名额
+    let (daily_request_limit, daily_token_limit, tpm_limit) = crate::commands::get_limits();
+    window
+        .state::<RateLimiter>()
+        .check(&rate_limit_key, daily_request_limit, daily_token_limit, tpm_limit)
+        .map_err(|e| e.to_string())?;
+
+    // 获取配置——持久化的运行时设置排在显式 override 之后、环境变量之前，
+    // 这样发布版也能不重启就切换 provider/model/key
+    let live_settings = window.state::<crate::ai_settings::AiSettingsStore>().get();
+    let config = AIConfig::from_env_and_overrides_with_auth(
+        provider_override.or(live_settings.provider),
+        model_override.or(live_settings.model),
+        api_key_override.or(live_settings.api_key),
+        base_url_override.or(live_settings.base_url),
+        auth_mode,
+        access_token_override,
+        auth_header_name_override,
+        auth_scheme_override,
     )?;
 
-    // 获取API密钥和base URL
-    let api_key = config.api_key.ok_or_else(|| {
-        format!("{:?} API key not configured", config.provider)
-    })?;
-    
-    let base_url = config.base_url.unwrap_or_else(|| {
+    // 解析认证方式对应的请求头；Anthropic 在默认的 API key 模式下走自己原生的
+    // x-api-key 方案，其余情况（含 access-token 网关）统一用通用 header
+    let (auth_header_name, auth_header_value) = config.resolve_auth_header()?;
+    let anthropic_native_api_key = if matches!(config.provider, AIProvider::Anthropic)
+        && config.auth_mode == crate::ai_chat::AuthMode::ApiKey
+    {
+        config.api_key.clone()
+    } else {
+        None
+    };
+
+    let base_url = config.base_url.clone().unwrap_or_else(|| {
         match config.provider {
             crate::ai_chat::AIProvider::OpenAI => "https://api.openai.com/v1".to_string(),
             crate::ai_chat::AIProvider::Anthropic => "https://api.anthropic.com/v1".to_string(),
             crate::ai_chat::AIProvider::DeepSeek => "https://api.deepseek.com/v1".to_string(),
+            crate::ai_chat::AIProvider::Cohere => "https://api.cohere.com/v1".to_string(),
             _ => "https://api.openai.com/v1".to_string(), // 默认使用OpenAI兼容格式
         }
     });
@@ -142,127 +258,447 @@ pub async fn chat_stream(
         String::new()
     };
 
-    // 构建消息
-    let mut messages = vec![json!({"role": "system", "content": system_prompt})];
-    if !xml_context.is_empty() {
-        messages.push(json!({"role": "system", "content": xml_context}));
-    }
-
-    for msg in request.messages {
-        let content = msg
-            .parts
-            .iter()
-            .filter_map(|part| {
-                if let crate::ai_chat::MessagePart::Text { text } = part {
-                    Some(text.clone())
-                } else {
-                    None
-                }
-            })
-            .collect::<Vec<_>>()
-            .join("\n");
-
-        messages.push(json!({"role": msg.role, "content": content}));
-    }
+    // 把前端的 UI 消息原样带下去，三个 provider 的 agent loop 各自把 parts
+    // （文本/图片/工具调用/工具结果）裹成自己要的请求形状，这样图片和工具调用
+    // 历史也能传到实际被前端调用的这条路径上，而不是只有 chat_stream_genai 支持
+    let turns: Vec<UIMessage> = request.messages;
+    let supports_images = provider_supports_images(&config.provider);
 
     // 发送开始事件
     window
         .emit("chat-stream", StreamEvent::Start)
         .map_err(|e| format!("Failed to emit start: {}", e))?;
 
-    // 创建HTTP客户端并发送请求
     let client = Client::new();
-    let tools = create_tools();
-    let request_body = json!({
-        "model": config.model_id,
-        "messages": messages,
-        "tools": tools,
-        "stream": true
-    });
+    let request_overrides = request.request_overrides.unwrap_or(serde_json::Value::Null);
 
-    let url = format!("{}/chat/completions", base_url);
-    println!("[DEBUG] Sending request to: {}", url);
-    
-    let response = client
-        .post(&url)
-        .header("Authorization", format!("Bearer {}", api_key))
-        .header("Content-Type", "application/json")
-        .json(&request_body)
-        .send()
+    let usage = match config.provider {
+        AIProvider::Anthropic => {
+            run_anthropic_agent_loop(
+                &window,
+                &client,
+                &base_url,
+                anthropic_native_api_key.as_deref(),
+                &auth_header_name,
+                &auth_header_value,
+                &config.model_id,
+                &system_prompt,
+                &xml_context,
+                turns,
+                supports_images,
+                &request_overrides,
+            )
+            .await?
+        }
+        AIProvider::Cohere => {
+            run_cohere_agent_loop(
+                &window,
+                &client,
+                &base_url,
+                &auth_header_name,
+                &auth_header_value,
+                &config.model_id,
+                &system_prompt,
+                &xml_context,
+                turns,
+                &request_overrides,
+            )
+            .await?
+        }
+        _ => {
+            run_openai_agent_loop(
+                &window,
+                &client,
+                &base_url,
+                &auth_header_name,
+                &auth_header_value,
+                &config.model_id,
+                &system_prompt,
+                &xml_context,
+                turns,
+                supports_images,
+                &request_overrides,
+            )
+            .await?
+        }
+    };
+
+    // 按实际用量给这个 key 扣费，供下一次请求时的限流检查使用
+    window.state::<RateLimiter>().debit(
+        &rate_limit_key,
+        usage.input_tokens + usage.output_tokens,
+        tpm_limit,
+    );
+
+    window
+        .emit("chat-stream", StreamEvent::Finish { usage: Some(usage) })
+        .map_err(|e| format!("Failed to emit finish: {}", e))?;
+
+    Ok(())
+}
+
+// 基于 genai crate 的流式聊天命令——直接走 genai 统一的 streaming API，不经过
+// 我们自己手写的各 provider SSE 解析。目前不支持工具调用循环（见 chat_stream），
+// 只把文本增量和最终 usage 通过 Tauri channel 推给前端。
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn chat_stream_genai(
+    window: Window,
+    payload: String,
+    provider_override: Option<String>,
+    model_override: Option<String>,
+    api_key_override: Option<String>,
+    base_url_override: Option<String>,
+    minimal_style: Option<bool>,
+    invoke_token: Option<String>,
+    channel: Channel<StreamEvent>,
+) -> Result<(), String> {
+    let request: ChatRequestPayload =
+        serde_json::from_str(&payload).map_err(|e| format!("Invalid request: {}", e))?;
+
+    // 和 chat_stream 一样的三道门：invoke token、access code、限流——这个命令
+    // 同样会直接消耗 provider 额度，不能因为走的是 genai 路径就绕过去
+    let session_id = request.session_id.clone().unwrap_or_default();
+    window
+        .state::<crate::invoke_auth::InvokeAuthGate>()
+        .verify(&session_id, invoke_token.as_deref())
+        .map_err(|e| e.to_string())?;
+
+    let rate_limit_key = request
+        .access_code
+        .clone()
+        .filter(|code| !code.is_empty())
+        .or_else(|| request.session_id.clone())
+        .unwrap_or_else(|| "anonymous".to_string());
+
+    if let Ok(access_codes_str) = std::env::var("ACCESS_CODE_LIST") {
+        let access_codes: Vec<String> = access_codes_str
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        if !access_codes.is_empty() {
+            let provided_code = request.access_code.clone().unwrap_or_default();
+            if !access_codes.contains(&provided_code) {
+                return Err("Invalid or missing access code".to_string());
+            }
+        }
+    }
+
+    let (daily_request_limit, daily_token_limit, tpm_limit) = crate::commands::get_limits();
+    window
+        .state::<RateLimiter>()
+        .check(&rate_limit_key, daily_request_limit, daily_token_limit, tpm_limit)
+        .map_err(|e| e.to_string())?;
+
+    let config = AIConfig::from_env_and_overrides(
+        provider_override,
+        model_override,
+        api_key_override,
+        base_url_override,
+    )?;
+
+    let client = create_client(&config).await?;
+
+    let system_prompt = get_system_prompt(&config.model_id, minimal_style.unwrap_or(false));
+    let mut messages = vec![ChatMessage::system(system_prompt)];
+    messages.extend(convert_ui_messages_to_genai(request.messages, &config.provider)?);
+
+    channel
+        .send(StreamEvent::Start)
+        .map_err(|e| format!("Failed to send start: {}", e))?;
+
+    let chat_req = ChatRequest::new(messages);
+    let mut chat_res = client
+        .exec_chat_stream(&config.model_id, chat_req, None)
         .await
-        .map_err(|e| format!("Request failed: {}", e))?;
+        .map_err(|e| format!("genai stream request failed: {}", e))?;
 
-    if !response.status().is_success() {
-        let status = response.status();
-        let error_text = response.text().await.unwrap_or_default();
-        return Err(format!("API error {}: {}", status, error_text));
+    let mut usage = UsageStats::default();
+
+    while let Some(event) = chat_res.stream.next().await {
+        match event {
+            Ok(ChatStreamEvent::Chunk(chunk)) => {
+                channel
+                    .send(StreamEvent::TextDelta { delta: chunk.content })
+                    .ok();
+            }
+            Ok(ChatStreamEvent::End(end)) => {
+                if let Some(captured_usage) = end.captured_usage {
+                    usage.input_tokens = captured_usage.prompt_tokens.unwrap_or(0) as u32;
+                    usage.output_tokens = captured_usage.completion_tokens.unwrap_or(0) as u32;
+                }
+            }
+            Ok(_) => {
+                // Start/ReasoningChunk/ToolCallChunk：genai 的工具调用桥接留到后续实现
+            }
+            Err(e) => {
+                let error = format!("genai stream error: {}", e);
+                channel.send(StreamEvent::Error { error: error.clone() }).ok();
+                return Err(error);
+            }
+        }
     }
 
-    // 处理SSE流
-    let mut stream = response.bytes_stream();
-    let mut buffer = String::new();
-    
-    // 跟踪工具调用的参数累积
-    let mut tool_call_args: std::collections::HashMap<String, (String, String)> = std::collections::HashMap::new();
-
-    let mut chunk_count = 0;
-    while let Some(chunk) = stream.next().await {
-        chunk_count += 1;
-        let chunk = chunk.map_err(|e| format!("Stream error: {}", e))?;
-        let chunk_str = String::from_utf8_lossy(&chunk);
-        buffer.push_str(&chunk_str);
-
-        // 处理SSE行 - 按单个\n分割
-        while let Some(pos) = buffer.find('\n') {
-            let line = buffer[..pos].trim().to_string();
-            buffer = buffer[pos + 1..].to_string();
-
-            if line.is_empty() || line == "data: [DONE]" {
-                continue;
+    window
+        .state::<RateLimiter>()
+        .debit(&rate_limit_key, usage.input_tokens + usage.output_tokens, tpm_limit);
+
+    channel
+        .send(StreamEvent::Finish { usage: Some(usage) })
+        .map_err(|e| format!("Failed to send finish: {}", e))?;
+
+    Ok(())
+}
+
+// 把一条 UI 消息的 parts 追加进 OpenAI `/chat/completions` 的 messages：文本/
+// 图片合并成一条 {role, content}（content 可能是字符串或 content block 数组）；
+// assistant 发起的 tool-call 变成一条带 tool_calls 的 assistant 消息，随后的
+// tool-result 变成一条 role=tool 的消息——和 convert_ui_messages_to_genai 思路
+// 一致，只是落地成 OpenAI 原生 JSON 而不是 genai 类型
+fn push_openai_turn(messages: &mut Vec<serde_json::Value>, msg: &UIMessage, supports_images: bool) {
+    let mut content_blocks = Vec::new();
+    let mut tool_calls = Vec::new();
+
+    for part in &msg.parts {
+        match part {
+            MessagePart::Text { text } => {
+                content_blocks.push(json!({"type": "text", "text": text}));
+            }
+            MessagePart::File { url, .. } if supports_images => {
+                content_blocks.push(file_part_to_openai_content(url));
+            }
+            MessagePart::File { url, .. } => {
+                content_blocks.push(json!({"type": "text", "text": image_omitted_message(url)}));
+            }
+            MessagePart::ToolCall { tool_call_id, tool_name, input } => {
+                tool_calls.push(json!({
+                    "id": tool_call_id,
+                    "type": "function",
+                    "function": {
+                        "name": tool_name,
+                        "arguments": serde_json::to_string(input).unwrap_or_default(),
+                    }
+                }));
+            }
+            MessagePart::ToolResult { tool_call_id, result, .. } => {
+                messages.push(json!({
+                    "role": "tool",
+                    "tool_call_id": tool_call_id,
+                    "content": serde_json::to_string(result).unwrap_or_default(),
+                }));
             }
+        }
+    }
+
+    if !tool_calls.is_empty() {
+        messages.push(json!({"role": "assistant", "tool_calls": tool_calls}));
+    }
+
+    if !content_blocks.is_empty() {
+        // 只有一段纯文本时退化成普通字符串 content，和没有图片/工具调用的旧行为保持一致
+        let content = if content_blocks.len() == 1 && content_blocks[0]["type"] == "text" {
+            content_blocks[0]["text"].clone()
+        } else {
+            serde_json::Value::Array(content_blocks)
+        };
+        messages.push(json!({"role": msg.role, "content": content}));
+    }
+}
+
+// OpenAI `/chat/completions` 兼容的 agent 循环（OpenAI、DeepSeek、Groq、Ollama 等走这条路）
+#[allow(clippy::too_many_arguments)]
+async fn run_openai_agent_loop(
+    window: &Window,
+    client: &Client,
+    base_url: &str,
+    auth_header_name: &str,
+    auth_header_value: &str,
+    model_id: &str,
+    system_prompt: &str,
+    xml_context: &str,
+    turns: Vec<UIMessage>,
+    supports_images: bool,
+    request_overrides: &serde_json::Value,
+) -> Result<UsageStats, String> {
+    // 构建消息
+    let mut messages = vec![json!({"role": "system", "content": system_prompt})];
+    if !xml_context.is_empty() {
+        messages.push(json!({"role": "system", "content": xml_context}));
+    }
+    for msg in &turns {
+        push_openai_turn(&mut messages, msg, supports_images);
+    }
+
+    let tools = create_tools();
+    let url = format!("{}/chat/completions", base_url);
+
+    let mut total_usage = UsageStats::default();
+
+    // agent 循环：每一步都可能产生工具调用，我们合成一条固定的成功/失败确认
+    // （而不是前端渲染 diagram 之后的真实结果——我们拿不到那个）作为 tool
+    // 消息喂回模型，直到模型给出 finish_reason == "stop" 且没有工具调用，
+    // 或达到步数上限。这足以让模型继续接着对话往下走（比如用 append_diagram
+    // 续写上一步被截断的 XML），但不构成对 diagram 是否真的渲染成功的校验
+    for step in 0..MAX_AGENT_STEPS {
+        if step > 0 {
+            window
+                .emit("chat-stream", StreamEvent::StepStart { step })
+                .ok();
+        }
+
+        let mut request_body = json!({
+            "model": model_id,
+            "messages": messages,
+            "tools": tools,
+            "stream": true,
+            // 不开这个开关，大多数 OpenAI 兼容网关的流式响应里不会带 usage 字段，
+            // 下面的 total_usage 累加和外层的限流/计费就都是摆设
+            "stream_options": {"include_usage": true}
+        });
+        merge_request_overrides(&mut request_body, request_overrides);
+
+        println!("[DEBUG] Sending request to: {} (step {})", url, step);
+
+        let response = client
+            .post(&url)
+            .header(auth_header_name, auth_header_value)
+            .header("Content-Type", "application/json")
+            .json(&request_body)
+            .send()
+            .await
+            .map_err(|e| format!("Request failed: {}", e))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(format!("API error {}: {}", status, error_text));
+        }
+
+        // 处理SSE流
+        let mut stream = response.bytes_stream();
+        let mut buffer = String::new();
+
+        // 跟踪工具调用的参数累积，按 delta.tool_calls[].index 归并——OpenAI 流式格式里
+        // 只有第一个分片带 id/name，后续分片只带 index 和一段 arguments 文本
+        let mut tool_call_args: std::collections::HashMap<u64, (String, String, String)> = std::collections::HashMap::new();
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| format!("Stream error: {}", e))?;
+            let chunk_str = String::from_utf8_lossy(&chunk);
+            buffer.push_str(&chunk_str);
+
+            // 处理SSE行 - 按单个\n分割
+            while let Some(pos) = buffer.find('\n') {
+                let line = buffer[..pos].trim().to_string();
+                buffer = buffer[pos + 1..].to_string();
+
+                if line.is_empty() || line == "data: [DONE]" {
+                    continue;
+                }
+
+                if let Some(data) = line.strip_prefix("data: ") {
+                    if let Ok(json_data) = serde_json::from_str::<serde_json::Value>(data) {
+                        // 累加这一步返回的 usage（部分 OpenAI 兼容网关在最后一个 chunk 中携带）
+                        if let Some(usage) = json_data.get("usage") {
+                            total_usage.input_tokens += usage
+                                .get("prompt_tokens")
+                                .and_then(|v| v.as_u64())
+                                .unwrap_or(0) as u32;
+                            total_usage.output_tokens += usage
+                                .get("completion_tokens")
+                                .and_then(|v| v.as_u64())
+                                .unwrap_or(0) as u32;
+                        }
+
+                        // 处理delta - 使用安全的get方法
+                        if let Some(choices) = json_data.get("choices").and_then(|v| v.as_array()) {
+                            if let Some(choice) = choices.first() {
+                                if let Some(delta) = choice.get("delta").and_then(|v| v.as_object()) {
+                                    // 文本内容
+                                    if let Some(content) = delta.get("content").and_then(|v| v.as_str()) {
+                                        if !content.is_empty() {
+                                            window.emit("chat-stream", StreamEvent::TextDelta {
+                                                delta: content.to_string(),
+                                            }).ok();
+                                        }
+                                    }
+
+                                    // 工具调用
+                                    if let Some(tool_calls) = delta.get("tool_calls").and_then(|v| v.as_array()) {
+                                        for tool_call in tool_calls {
+                                            let index = tool_call.get("index").and_then(|v| v.as_u64());
+                                            let Some(index) = index else { continue };
+
+                                            let id = tool_call.get("id").and_then(|v| v.as_str());
+                                            let function = tool_call.get("function").and_then(|v| v.as_object());
+                                            let name = function.and_then(|f| f.get("name")).and_then(|v| v.as_str());
+
+                                            // 第一个分片才带 id/name，初始化这个 index 的记录
+                                            if id.is_some() || name.is_some() {
+                                                let entry = tool_call_args.entry(index).or_insert_with(|| {
+                                                    (String::new(), String::new(), String::new())
+                                                });
+                                                if let Some(id) = id {
+                                                    entry.0 = id.to_string();
+                                                }
+                                                if let Some(name) = name {
+                                                    entry.1 = name.to_string();
+                                                }
+                                                window
+                                                    .emit("chat-stream", StreamEvent::ToolCallStart {
+                                                        tool_call_id: entry.0.clone(),
+                                                        tool_name: entry.1.clone(),
+                                                    })
+                                                    .ok();
+                                            }
+
+                                            if let Some(args) = function.and_then(|f| f.get("arguments")).and_then(|v| v.as_str()) {
+                                                // 累积参数——无论这个分片是否带 id，都按 index 追加
+                                                let entry = tool_call_args.entry(index).or_insert_with(|| {
+                                                    (String::new(), String::new(), String::new())
+                                                });
+                                                entry.2.push_str(args);
 
-            if let Some(data) = line.strip_prefix("data: ") {
-                if let Ok(json_data) = serde_json::from_str::<serde_json::Value>(data) {
-                    // 处理delta - 使用安全的get方法
-                    if let Some(choices) = json_data.get("choices").and_then(|v| v.as_array()) {
-                        if let Some(choice) = choices.first() {
-                            if let Some(delta) = choice.get("delta").and_then(|v| v.as_object()) {
-                                // 文本内容
-                                if let Some(content) = delta.get("content").and_then(|v| v.as_str()) {
-                                    if !content.is_empty() {
-                                        window.emit("chat-stream", StreamEvent::TextDelta {
-                                            delta: content.to_string(),
-                                        }).ok();
+                                                window
+                                                    .emit("chat-stream", StreamEvent::ToolInputDelta {
+                                                        tool_call_id: entry.0.clone(),
+                                                        delta: args.to_string(),
+                                                    })
+                                                    .ok();
+                                            }
+                                        }
                                     }
                                 }
 
-                                // 工具调用
-                                if let Some(tool_calls) = delta.get("tool_calls").and_then(|v| v.as_array()) {
-                                    for tool_call in tool_calls {
-                                        if let Some(id) = tool_call.get("id").and_then(|v| v.as_str()) {
-                                            if let Some(function) = tool_call.get("function").and_then(|v| v.as_object()) {
-                                                if let Some(name) = function.get("name").and_then(|v| v.as_str()) {
-                                                    // 初始化工具调用记录
-                                                    tool_call_args.entry(id.to_string())
-                                                        .or_insert((name.to_string(), String::new()));
-                                                    
+                                // 检查是否完成
+                                if let Some(finish_reason) = choice.get("finish_reason").and_then(|v| v.as_str()) {
+                                    if finish_reason == "stop" || finish_reason == "tool_calls" {
+                                        // 发送所有工具调用的complete事件，按 index 顺序遍历——
+                                        // tool_call_args 是 HashMap，不排序的话多个工具调用的
+                                        // 事件顺序会是不确定的
+                                        let mut ordered: Vec<_> = tool_call_args.iter().collect();
+                                        ordered.sort_by_key(|(index, _)| **index);
+                                        for (_, (tool_call_id, tool_name, args_str)) in ordered {
+                                            match serde_json::from_str::<serde_json::Value>(args_str) {
+                                                Ok(input_json) => {
                                                     window
-                                                        .emit("chat-stream", StreamEvent::ToolCallStart {
-                                                            tool_call_id: id.to_string(),
-                                                            tool_name: name.to_string(),
+                                                        .emit("chat-stream", StreamEvent::ToolInputComplete {
+                                                            tool_call_id: tool_call_id.clone(),
+                                                            tool_name: tool_name.clone(),
+                                                            input: input_json,
                                                         })
                                                         .ok();
                                                 }
-                                                if let Some(args) = function.get("arguments").and_then(|v| v.as_str()) {
-                                                    // 累积参数
-                                                    if let Some((_, accumulated_args)) = tool_call_args.get_mut(id) {
-                                                        accumulated_args.push_str(args);
-                                                    }
-                                                    
+                                                Err(_) => {
                                                     window
-                                                        .emit("chat-stream", StreamEvent::ToolInputDelta {
-                                                            tool_call_id: id.to_string(),
-                                                            delta: args.to_string(),
+                                                        .emit("chat-stream", StreamEvent::Error {
+                                                            error: format!(
+                                                                "Tool call '{}' produced invalid JSON arguments: {}",
+                                                                tool_name,
+                                                                truncate_for_error(args_str)
+                                                            ),
                                                         })
                                                         .ok();
                                                 }
@@ -271,35 +707,579 @@ pub async fn chat_stream(
                                     }
                                 }
                             }
-                            
-                            // 检查是否完成
-                            if let Some(finish_reason) = choice.get("finish_reason").and_then(|v| v.as_str()) {
-                                if finish_reason == "stop" || finish_reason == "tool_calls" {
-                                    // 发送所有工具调用的complete事件
-                                    for (tool_call_id, (tool_name, args_str)) in &tool_call_args {
-                                        if let Ok(input_json) = serde_json::from_str::<serde_json::Value>(args_str) {
+                        }
+                    }
+                }
+            }
+        }
+
+        if tool_call_args.is_empty() {
+            // 模型没有再发起工具调用，agent 循环结束
+            break;
+        }
+
+        // 把这一步模型发起的工具调用和（合成的）工具执行结果喂回 messages，继续下一步
+        let mut ordered_calls: Vec<_> = tool_call_args.into_iter().collect();
+        ordered_calls.sort_by_key(|(index, _)| *index);
+
+        let tool_calls_json: Vec<serde_json::Value> = ordered_calls
+            .iter()
+            .map(|(_, (id, name, args))| {
+                json!({
+                    "id": id,
+                    "type": "function",
+                    "function": { "name": name, "arguments": args }
+                })
+            })
+            .collect();
+
+        messages.push(json!({
+            "role": "assistant",
+            "content": serde_json::Value::Null,
+            "tool_calls": tool_calls_json,
+        }));
+
+        for (_, (id, name, _args)) in &ordered_calls {
+            messages.push(json!({
+                "role": "tool",
+                "tool_call_id": id,
+                "content": format!("{} executed successfully", name),
+            }));
+        }
+
+        if step + 1 == MAX_AGENT_STEPS {
+            window
+                .emit("chat-stream", StreamEvent::Error {
+                    error: format!("Agent loop exceeded the maximum of {} steps", MAX_AGENT_STEPS),
+                })
+                .ok();
+            return Err("Agent loop exceeded max steps".to_string());
+        }
+    }
+
+    // 把所有步骤累计的 usage 交回调用方，由它统一发送完成事件并记账
+    Ok(total_usage)
+}
+
+// 把一条 UI 消息的 parts 追加进 Anthropic messages API 的 messages：文本/图片
+// 合并成一条 user/assistant 消息（Anthropic 只认识这两种角色，其余角色如遗留
+// 的 "system" 折叠成 user）；assistant 发起的 tool-call 变成一条 tool_use
+// content block 的 assistant 消息，随后的 tool-result 变成一条 tool_result
+// content block 的 user 消息
+fn push_anthropic_turn(messages: &mut Vec<serde_json::Value>, msg: &UIMessage, supports_images: bool) {
+    let mut content_blocks = Vec::new();
+    let mut tool_use_blocks = Vec::new();
+    let mut tool_result_blocks = Vec::new();
+
+    for part in &msg.parts {
+        match part {
+            MessagePart::Text { text } => {
+                content_blocks.push(json!({"type": "text", "text": text}));
+            }
+            MessagePart::File { url, media_type } if supports_images => {
+                content_blocks.push(file_part_to_anthropic_content(url, media_type.as_deref()));
+            }
+            MessagePart::File { url, .. } => {
+                content_blocks.push(json!({"type": "text", "text": image_omitted_message(url)}));
+            }
+            MessagePart::ToolCall { tool_call_id, tool_name, input } => {
+                tool_use_blocks.push(json!({
+                    "type": "tool_use",
+                    "id": tool_call_id,
+                    "name": tool_name,
+                    "input": input,
+                }));
+            }
+            MessagePart::ToolResult { tool_call_id, result, .. } => {
+                tool_result_blocks.push(json!({
+                    "type": "tool_result",
+                    "tool_use_id": tool_call_id,
+                    "content": serde_json::to_string(result).unwrap_or_default(),
+                }));
+            }
+        }
+    }
+
+    if !content_blocks.is_empty() {
+        let role = if msg.role == "assistant" { "assistant" } else { "user" };
+        // 只有一段纯文本时退化成普通字符串 content，和没有图片/工具调用的旧行为保持一致
+        let content = if content_blocks.len() == 1 && content_blocks[0]["type"] == "text" {
+            content_blocks[0]["text"].clone()
+        } else {
+            serde_json::Value::Array(content_blocks)
+        };
+        messages.push(json!({"role": role, "content": content}));
+    }
+    if !tool_use_blocks.is_empty() {
+        messages.push(json!({"role": "assistant", "content": tool_use_blocks}));
+    }
+    if !tool_result_blocks.is_empty() {
+        messages.push(json!({"role": "user", "content": tool_result_blocks}));
+    }
+}
+
+// Anthropic `/v1/messages` 的 agent 循环——请求/响应形状和 OpenAI 完全不同：
+// system 是顶层字段而不是一条消息，tools 是扁平的 input_schema，流式事件是
+// content_block_start/content_block_delta/content_block_stop/message_delta。
+#[allow(clippy::too_many_arguments)]
+async fn run_anthropic_agent_loop(
+    window: &Window,
+    client: &Client,
+    base_url: &str,
+    // Anthropic 原生的 x-api-key 鉴权；为 None 时说明调用方配置了 access-token 网关，
+    // 改用通用的 (auth_header_name, auth_header_value) 鉴权
+    native_api_key: Option<&str>,
+    auth_header_name: &str,
+    auth_header_value: &str,
+    model_id: &str,
+    system_prompt: &str,
+    xml_context: &str,
+    turns: Vec<UIMessage>,
+    supports_images: bool,
+    request_overrides: &serde_json::Value,
+) -> Result<UsageStats, String> {
+    let system = if xml_context.is_empty() {
+        system_prompt.to_string()
+    } else {
+        format!("{}\n\n{}", system_prompt, xml_context)
+    };
+
+    let mut messages: Vec<serde_json::Value> = Vec::new();
+    for msg in &turns {
+        push_anthropic_turn(&mut messages, msg, supports_images);
+    }
+
+    let tools = create_anthropic_tools();
+    let url = format!("{}/messages", base_url);
+
+    let mut total_usage = UsageStats::default();
+
+    for step in 0..MAX_AGENT_STEPS {
+        if step > 0 {
+            window
+                .emit("chat-stream", StreamEvent::StepStart { step })
+                .ok();
+        }
+
+        let mut request_body = json!({
+            "model": model_id,
+            "system": system,
+            "messages": messages,
+            "tools": tools,
+            "max_tokens": 8192,
+            "stream": true
+        });
+        merge_request_overrides(&mut request_body, request_overrides);
+
+        println!("[DEBUG] Sending request to: {} (step {})", url, step);
+
+        let mut request_builder = client
+            .post(&url)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .header("Content-Type", "application/json");
+        request_builder = match native_api_key {
+            Some(key) => request_builder.header("x-api-key", key),
+            None => request_builder.header(auth_header_name, auth_header_value),
+        };
+
+        let response = request_builder
+            .json(&request_body)
+            .send()
+            .await
+            .map_err(|e| format!("Request failed: {}", e))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(format!("API error {}: {}", status, error_text));
+        }
+
+        let mut stream = response.bytes_stream();
+        let mut buffer = String::new();
+
+        // content_block 的 index -> (tool_call_id, tool_name, 累积的 input_json_delta 片段)
+        let mut tool_blocks: std::collections::HashMap<u64, (String, String, String)> = std::collections::HashMap::new();
+        let mut stop_reason: Option<String> = None;
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| format!("Stream error: {}", e))?;
+            let chunk_str = String::from_utf8_lossy(&chunk);
+            buffer.push_str(&chunk_str);
+
+            while let Some(pos) = buffer.find('\n') {
+                let line = buffer[..pos].trim().to_string();
+                buffer = buffer[pos + 1..].to_string();
+
+                if line.is_empty() {
+                    continue;
+                }
+
+                let Some(data) = line.strip_prefix("data: ") else { continue };
+                let Ok(event) = serde_json::from_str::<serde_json::Value>(data) else { continue };
+                let event_type = event.get("type").and_then(|v| v.as_str()).unwrap_or("");
+
+                match event_type {
+                    "content_block_start" => {
+                        let index = event.get("index").and_then(|v| v.as_u64()).unwrap_or(0);
+                        if let Some(block) = event.get("content_block") {
+                            if block.get("type").and_then(|v| v.as_str()) == Some("tool_use") {
+                                let id = block.get("id").and_then(|v| v.as_str()).unwrap_or_default().to_string();
+                                let name = block.get("name").and_then(|v| v.as_str()).unwrap_or_default().to_string();
+
+                                window
+                                    .emit("chat-stream", StreamEvent::ToolCallStart {
+                                        tool_call_id: id.clone(),
+                                        tool_name: name.clone(),
+                                    })
+                                    .ok();
+
+                                tool_blocks.insert(index, (id, name, String::new()));
+                            }
+                        }
+                    }
+                    "content_block_delta" => {
+                        let index = event.get("index").and_then(|v| v.as_u64()).unwrap_or(0);
+                        if let Some(delta) = event.get("delta") {
+                            match delta.get("type").and_then(|v| v.as_str()) {
+                                Some("text_delta") => {
+                                    if let Some(text) = delta.get("text").and_then(|v| v.as_str()) {
+                                        window
+                                            .emit("chat-stream", StreamEvent::TextDelta { delta: text.to_string() })
+                                            .ok();
+                                    }
+                                }
+                                Some("input_json_delta") => {
+                                    if let Some(partial) = delta.get("partial_json").and_then(|v| v.as_str()) {
+                                        if let Some((id, _name, args)) = tool_blocks.get_mut(&index) {
+                                            args.push_str(partial);
                                             window
-                                                .emit("chat-stream", StreamEvent::ToolInputComplete {
-                                                    tool_call_id: tool_call_id.clone(),
-                                                    tool_name: tool_name.clone(),
-                                                    input: input_json,
+                                                .emit("chat-stream", StreamEvent::ToolInputDelta {
+                                                    tool_call_id: id.clone(),
+                                                    delta: partial.to_string(),
                                                 })
                                                 .ok();
                                         }
                                     }
                                 }
+                                _ => {}
                             }
                         }
                     }
+                    "content_block_stop" => {
+                        let index = event.get("index").and_then(|v| v.as_u64()).unwrap_or(0);
+                        if let Some((id, name, args)) = tool_blocks.get(&index) {
+                            match serde_json::from_str::<serde_json::Value>(args) {
+                                Ok(input_json) => {
+                                    window
+                                        .emit("chat-stream", StreamEvent::ToolInputComplete {
+                                            tool_call_id: id.clone(),
+                                            tool_name: name.clone(),
+                                            input: input_json,
+                                        })
+                                        .ok();
+                                }
+                                Err(_) => {
+                                    window
+                                        .emit("chat-stream", StreamEvent::Error {
+                                            error: format!(
+                                                "Tool call '{}' produced invalid JSON arguments: {}",
+                                                name,
+                                                truncate_for_error(args)
+                                            ),
+                                        })
+                                        .ok();
+                                }
+                            }
+                        }
+                    }
+                    "message_delta" => {
+                        if let Some(reason) = event
+                            .get("delta")
+                            .and_then(|d| d.get("stop_reason"))
+                            .and_then(|v| v.as_str())
+                        {
+                            stop_reason = Some(reason.to_string());
+                        }
+                        if let Some(usage) = event.get("usage") {
+                            total_usage.output_tokens += usage
+                                .get("output_tokens")
+                                .and_then(|v| v.as_u64())
+                                .unwrap_or(0) as u32;
+                        }
+                    }
+                    "message_start" => {
+                        if let Some(usage) = event.get("message").and_then(|m| m.get("usage")) {
+                            total_usage.input_tokens += usage
+                                .get("input_tokens")
+                                .and_then(|v| v.as_u64())
+                                .unwrap_or(0) as u32;
+                        }
+                    }
+                    _ => {}
                 }
             }
         }
+
+        if tool_blocks.is_empty() || stop_reason.as_deref() != Some("tool_use") {
+            break;
+        }
+
+        // 把这一步的 tool_use 块和合成的 tool_result 喂回 messages，继续下一步
+        let mut ordered: Vec<_> = tool_blocks.into_iter().collect();
+        ordered.sort_by_key(|(index, _)| *index);
+
+        let content_blocks: Vec<serde_json::Value> = ordered
+            .iter()
+            .map(|(_, (id, name, args))| {
+                let input: serde_json::Value =
+                    serde_json::from_str(args).unwrap_or(serde_json::Value::Object(Default::default()));
+                json!({"type": "tool_use", "id": id, "name": name, "input": input})
+            })
+            .collect();
+
+        messages.push(json!({"role": "assistant", "content": content_blocks}));
+
+        let tool_results: Vec<serde_json::Value> = ordered
+            .iter()
+            .map(|(_, (id, name, _args))| {
+                json!({
+                    "type": "tool_result",
+                    "tool_use_id": id,
+                    "content": format!("{} executed successfully", name),
+                })
+            })
+            .collect();
+
+        messages.push(json!({"role": "user", "content": tool_results}));
+
+        if step + 1 == MAX_AGENT_STEPS {
+            window
+                .emit("chat-stream", StreamEvent::Error {
+                    error: format!("Agent loop exceeded the maximum of {} steps", MAX_AGENT_STEPS),
+                })
+                .ok();
+            return Err("Agent loop exceeded max steps".to_string());
+        }
     }
 
-    // 发送完成事件
-    window
-        .emit("chat-stream", StreamEvent::Finish { usage: None })
-        .map_err(|e| format!("Failed to emit finish: {}", e))?;
+    Ok(total_usage)
+}
 
-    Ok(())
+// Cohere 没有 OpenAI/Anthropic 那样的原生 content block / tool_calls 历史字段
+// （它自己的工具调用走下面这个 loop 合成的 call/outputs 格式），UI 历史里的图片
+// 和工具调用/结果在这里没有对应的原生表达，降级成一段文字提示而不是静默丢弃
+fn flatten_cohere_turn(msg: &UIMessage) -> String {
+    msg.parts
+        .iter()
+        .map(|part| match part {
+            MessagePart::Text { text } => text.clone(),
+            MessagePart::File { url, .. } => image_omitted_message(url),
+            MessagePart::ToolCall { tool_name, .. } => format!("[Tool call: {}]", tool_name),
+            MessagePart::ToolResult { tool_name, result, .. } => format!(
+                "[Tool result for {}: {}]",
+                tool_name,
+                serde_json::to_string(result).unwrap_or_default()
+            ),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Cohere `/v1/chat` 的 agent 循环——当前轮次放在 `message`，之前的轮次放在
+// `chat_history`（USER/CHATBOT），系统提示放在 `preamble`，工具调用结果通过
+// `tool_results` 字段喂回模型。流式响应是换行分隔的 JSON（不是 SSE "data: " 前缀）。
+#[allow(clippy::too_many_arguments)]
+async fn run_cohere_agent_loop(
+    window: &Window,
+    client: &Client,
+    base_url: &str,
+    auth_header_name: &str,
+    auth_header_value: &str,
+    model_id: &str,
+    system_prompt: &str,
+    xml_context: &str,
+    turns: Vec<UIMessage>,
+    request_overrides: &serde_json::Value,
+) -> Result<UsageStats, String> {
+    let preamble = if xml_context.is_empty() {
+        system_prompt.to_string()
+    } else {
+        format!("{}\n\n{}", system_prompt, xml_context)
+    };
+
+    // 把除了最后一条之外的轮次放进 chat_history，最后一条作为本轮的 message
+    let mut chat_history: Vec<serde_json::Value> = Vec::new();
+    let mut pending_message = String::new();
+    let mut turns: Vec<(String, String)> = turns
+        .iter()
+        .map(|msg| (msg.role.clone(), flatten_cohere_turn(msg)))
+        .collect();
+    if let Some((role, content)) = turns.pop() {
+        for (role, content) in turns {
+            chat_history.push(json!({
+                "role": if role == "assistant" { "CHATBOT" } else { "USER" },
+                "message": content,
+            }));
+        }
+        if role == "assistant" {
+            // 理论上不会出现最后一条是 assistant 的情况，保守地折进历史
+            chat_history.push(json!({"role": "CHATBOT", "message": content}));
+        } else {
+            pending_message = content;
+        }
+    }
+
+    let tools = create_cohere_tools();
+    let url = format!("{}/chat", base_url);
+
+    let mut total_usage = UsageStats::default();
+    let mut pending_tool_results: Option<Vec<serde_json::Value>> = None;
+
+    for step in 0..MAX_AGENT_STEPS {
+        if step > 0 {
+            window
+                .emit("chat-stream", StreamEvent::StepStart { step })
+                .ok();
+        }
+
+        let mut request_body = json!({
+            "model": model_id,
+            "message": pending_message,
+            "chat_history": chat_history,
+            "preamble": preamble,
+            "tools": tools,
+            "stream": true
+        });
+        if let Some(tool_results) = &pending_tool_results {
+            request_body["tool_results"] = json!(tool_results);
+        }
+        merge_request_overrides(&mut request_body, request_overrides);
+
+        println!("[DEBUG] Sending request to: {} (step {})", url, step);
+
+        let response = client
+            .post(&url)
+            .header(auth_header_name, auth_header_value)
+            .header("Content-Type", "application/json")
+            .json(&request_body)
+            .send()
+            .await
+            .map_err(|e| format!("Request failed: {}", e))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(format!("API error {}: {}", status, error_text));
+        }
+
+        let mut stream = response.bytes_stream();
+        let mut buffer = String::new();
+
+        let mut tool_calls: Vec<(String, String, serde_json::Value)> = Vec::new();
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| format!("Stream error: {}", e))?;
+            let chunk_str = String::from_utf8_lossy(&chunk);
+            buffer.push_str(&chunk_str);
+
+            while let Some(pos) = buffer.find('\n') {
+                let line = buffer[..pos].trim().to_string();
+                buffer = buffer[pos + 1..].to_string();
+
+                if line.is_empty() {
+                    continue;
+                }
+
+                let Ok(event) = serde_json::from_str::<serde_json::Value>(&line) else { continue };
+                let event_type = event.get("event_type").and_then(|v| v.as_str()).unwrap_or("");
+
+                match event_type {
+                    "text-generation" => {
+                        if let Some(text) = event.get("text").and_then(|v| v.as_str()) {
+                            window
+                                .emit("chat-stream", StreamEvent::TextDelta { delta: text.to_string() })
+                                .ok();
+                        }
+                    }
+                    "tool-calls-generation" => {
+                        if let Some(calls) = event.get("tool_calls").and_then(|v| v.as_array()) {
+                            for (idx, call) in calls.iter().enumerate() {
+                                let name = call.get("name").and_then(|v| v.as_str()).unwrap_or_default().to_string();
+                                let parameters = call.get("parameters").cloned().unwrap_or(json!({}));
+                                let tool_call_id = format!("cohere-{}-{}", step, idx);
+
+                                window
+                                    .emit("chat-stream", StreamEvent::ToolCallStart {
+                                        tool_call_id: tool_call_id.clone(),
+                                        tool_name: name.clone(),
+                                    })
+                                    .ok();
+                                window
+                                    .emit("chat-stream", StreamEvent::ToolInputComplete {
+                                        tool_call_id: tool_call_id.clone(),
+                                        tool_name: name.clone(),
+                                        input: parameters.clone(),
+                                    })
+                                    .ok();
+
+                                tool_calls.push((name, tool_call_id, parameters));
+                            }
+                        }
+                    }
+                    "stream-end" => {
+                        if let Some(tokens) = event.get("response").and_then(|r| r.get("meta")).and_then(|m| m.get("tokens")) {
+                            total_usage.input_tokens += tokens
+                                .get("input_tokens")
+                                .and_then(|v| v.as_u64())
+                                .unwrap_or(0) as u32;
+                            total_usage.output_tokens += tokens
+                                .get("output_tokens")
+                                .and_then(|v| v.as_u64())
+                                .unwrap_or(0) as u32;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if tool_calls.is_empty() {
+            break;
+        }
+
+        // 把这一步的 assistant 发言和工具调用计入历史，并把（合成的）工具执行结果
+        // 放进 tool_results，供下一步的请求使用；下一步的 message 留空继续同一轮对话
+        chat_history.push(json!({
+            "role": "CHATBOT",
+            "message": "",
+            "tool_calls": tool_calls
+                .iter()
+                .map(|(name, _, parameters)| json!({"name": name, "parameters": parameters}))
+                .collect::<Vec<_>>(),
+        }));
+
+        pending_tool_results = Some(
+            tool_calls
+                .iter()
+                .map(|(name, _, parameters)| {
+                    json!({
+                        "call": {"name": name, "parameters": parameters},
+                        "outputs": [{"result": format!("{} executed successfully", name)}],
+                    })
+                })
+                .collect(),
+        );
+        pending_message = String::new();
+
+        if step + 1 == MAX_AGENT_STEPS {
+            window
+                .emit("chat-stream", StreamEvent::Error {
+                    error: format!("Agent loop exceeded the maximum of {} steps", MAX_AGENT_STEPS),
+                })
+                .ok();
+            return Err("Agent loop exceeded max steps".to_string());
+        }
+    }
+
+    Ok(total_usage)
 }
@@ -0,0 +1,96 @@
+// 运行时可改的 AI provider 配置，持久化在应用数据目录里。发布版没有 shell 可用
+// 时 `.env` 根本改不了，这是用户切换 provider/model/key 的唯一途径，
+// chat_stream 在每次请求时都会读取最新值，不需要重启应用。
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tauri::Manager;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AiSettings {
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub api_key: Option<String>,
+    pub base_url: Option<String>,
+}
+
+pub struct AiSettingsStore {
+    settings: Mutex<AiSettings>,
+    config_path: PathBuf,
+}
+
+impl AiSettingsStore {
+    pub fn new(app: &tauri::AppHandle) -> Self {
+        let config_path = app
+            .path()
+            .app_data_dir()
+            .map(|dir| dir.join("ai_settings.json"))
+            .unwrap_or_else(|_| PathBuf::from("ai_settings.json"));
+
+        let persisted_config_existed = config_path.exists();
+
+        let settings = std::fs::read_to_string(&config_path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<AiSettings>(&content).ok())
+            // 还没有持久化配置，说明是第一次启动：把现有的 .env/环境变量迁移过来，
+            // 这样用户原来的配置不会在升级后凭空消失
+            .unwrap_or_else(migrate_from_env);
+
+        let store = Self {
+            settings: Mutex::new(settings),
+            config_path,
+        };
+        if !persisted_config_existed {
+            store.save();
+        }
+        store
+    }
+
+    fn save(&self) {
+        if let Some(parent) = self.config_path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        if let Ok(settings) = self.settings.lock() {
+            if let Ok(json) = serde_json::to_string_pretty(&*settings) {
+                let _ = std::fs::write(&self.config_path, json);
+            }
+        }
+    }
+
+    pub fn get(&self) -> AiSettings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, settings: AiSettings) {
+        *self.settings.lock().unwrap() = settings;
+        self.save();
+    }
+}
+
+// 按 provider 对应的环境变量名把 .env 时代的配置读出来
+fn migrate_from_env() -> AiSettings {
+    let provider = std::env::var("AI_PROVIDER").ok();
+    let model = std::env::var("AI_MODEL").ok();
+
+    let api_key = provider.as_deref().and_then(|p| match p.to_lowercase().as_str() {
+        "openai" => std::env::var("OPENAI_API_KEY").ok(),
+        "anthropic" => std::env::var("ANTHROPIC_API_KEY").ok(),
+        "gemini" | "google" => std::env::var("GEMINI_API_KEY")
+            .ok()
+            .or_else(|| std::env::var("GOOGLE_GENERATIVE_AI_API_KEY").ok()),
+        "deepseek" => std::env::var("DEEPSEEK_API_KEY").ok(),
+        "groq" => std::env::var("GROQ_API_KEY").ok(),
+        "cohere" => std::env::var("COHERE_API_KEY").ok(),
+        _ => None,
+    });
+
+    let base_url = provider.as_deref().and_then(|p| match p.to_lowercase().as_str() {
+        "openai" => std::env::var("OPENAI_BASE_URL").ok(),
+        "anthropic" => std::env::var("ANTHROPIC_BASE_URL").ok(),
+        "deepseek" => std::env::var("DEEPSEEK_BASE_URL").ok(),
+        "ollama" => std::env::var("OLLAMA_BASE_URL").ok(),
+        _ => None,
+    });
+
+    AiSettings { provider, model, api_key, base_url }
+}
@@ -1,5 +1,11 @@
+use crate::ai_settings::{AiSettings, AiSettingsStore};
+use crate::hotkeys::HotkeyRegistry;
+use crate::invoke_auth::InvokeAuthGate;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::env;
+use tauri::Manager;
+use tauri_plugin_updater::UpdaterExt;
 
 // Configuration response
 #[derive(Debug, Serialize)]
@@ -21,31 +27,49 @@ pub struct VerifyAccessCodeRequest {
 pub struct VerifyAccessCodeResponse {
     valid: bool,
     message: String,
+    // 校验通过时签发，chat_stream 之后的每次调用都要带上它
+    #[serde(skip_serializing_if = "Option::is_none")]
+    invoke_token: Option<String>,
 }
 
-// Get configuration from environment variables
-#[tauri::command]
-pub fn get_config() -> Result<ConfigResponse, String> {
-    Ok(ConfigResponse {
-        access_code_required: env::var("ACCESS_CODE_LIST").is_ok(),
-        daily_request_limit: env::var("DAILY_REQUEST_LIMIT")
+// (daily_request_limit, daily_token_limit, tpm_limit) 读取自环境变量，0 表示不限。
+// get_config 和 ai_commands::chat_stream 的限流检查共用这份解析逻辑。
+pub(crate) fn get_limits() -> (u32, u32, u32) {
+    (
+        env::var("DAILY_REQUEST_LIMIT")
             .ok()
             .and_then(|v| v.parse().ok())
             .unwrap_or(0),
-        daily_token_limit: env::var("DAILY_TOKEN_LIMIT")
+        env::var("DAILY_TOKEN_LIMIT")
             .ok()
             .and_then(|v| v.parse().ok())
             .unwrap_or(0),
-        tpm_limit: env::var("TPM_LIMIT")
+        env::var("TPM_LIMIT")
             .ok()
             .and_then(|v| v.parse().ok())
             .unwrap_or(0),
+    )
+}
+
+// Get configuration from environment variables
+#[tauri::command]
+pub fn get_config() -> Result<ConfigResponse, String> {
+    let (daily_request_limit, daily_token_limit, tpm_limit) = get_limits();
+    Ok(ConfigResponse {
+        access_code_required: env::var("ACCESS_CODE_LIST").is_ok(),
+        daily_request_limit,
+        daily_token_limit,
+        tpm_limit,
     })
 }
 
 // Verify access code
 #[tauri::command]
-pub fn verify_access_code(access_code: String) -> Result<VerifyAccessCodeResponse, String> {
+pub fn verify_access_code(
+    app: tauri::AppHandle,
+    access_code: String,
+    session_id: String,
+) -> Result<VerifyAccessCodeResponse, String> {
     let access_codes_str = env::var("ACCESS_CODE_LIST").unwrap_or_default();
     let access_codes: Vec<String> = access_codes_str
         .split(',')
@@ -58,6 +82,7 @@ pub fn verify_access_code(access_code: String) -> Result<VerifyAccessCodeRespons
         return Ok(VerifyAccessCodeResponse {
             valid: true,
             message: "No access code required".to_string(),
+            invoke_token: None,
         });
     }
 
@@ -65,18 +90,105 @@ pub fn verify_access_code(access_code: String) -> Result<VerifyAccessCodeRespons
         return Ok(VerifyAccessCodeResponse {
             valid: false,
             message: "Access code is required".to_string(),
+            invoke_token: None,
         });
     }
 
     if access_codes.contains(&access_code) {
+        let invoke_token = app.state::<InvokeAuthGate>().issue(&session_id);
         Ok(VerifyAccessCodeResponse {
             valid: true,
             message: "Access code is valid".to_string(),
+            invoke_token: Some(invoke_token),
         })
     } else {
         Ok(VerifyAccessCodeResponse {
             valid: false,
             message: "Invalid access code".to_string(),
+            invoke_token: None,
         })
     }
 }
+
+// 可用更新的摘要，连同这台机器能不能走自带更新弹窗一起交给前端
+#[derive(Debug, Serialize)]
+pub struct UpdateSummary {
+    version: String,
+    dialog_supported: bool,
+}
+
+// 对话框模式的更新体验在 AppImage 和开发构建上是稳的；deb/rpm/msi 等其他打包
+// 形式下容易出问题（权限、沙箱、安装路径各异），所以只在这些场景打开自带弹窗，
+// 其余场景交给前端走手动的 check_for_update -> install_update 流程
+fn dialog_update_supported() -> bool {
+    cfg!(debug_assertions) || env::var("APPIMAGE").is_ok()
+}
+
+// 检查是否有可用更新，不负责下载安装
+#[tauri::command]
+pub async fn check_for_update(app: tauri::AppHandle) -> Result<Option<UpdateSummary>, String> {
+    let updater = app.updater().map_err(|e| format!("Failed to get updater: {}", e))?;
+    let update = updater
+        .check()
+        .await
+        .map_err(|e| format!("Failed to check for updates: {}", e))?;
+
+    Ok(update.map(|update| UpdateSummary {
+        version: update.version.clone(),
+        dialog_supported: dialog_update_supported(),
+    }))
+}
+
+// 手动流程的下载 + 安装；成功后重启应用让新版本生效
+#[tauri::command]
+pub async fn install_update(app: tauri::AppHandle) -> Result<(), String> {
+    let updater = app.updater().map_err(|e| format!("Failed to get updater: {}", e))?;
+    let update = updater
+        .check()
+        .await
+        .map_err(|e| format!("Failed to check for updates: {}", e))?
+        .ok_or_else(|| "No update available".to_string())?;
+
+    update
+        .download_and_install(|_chunk_len, _total| {}, || {})
+        .await
+        .map_err(|e| format!("Failed to install update: {}", e))?;
+
+    app.restart();
+}
+
+// 绑定一个全局快捷键到某个 action（open_chat / rerun_last_prompt / regenerate_diagram 等），
+// 同一个 action 可以绑定多个快捷键组合
+#[tauri::command]
+pub fn register_hotkey(
+    app: tauri::AppHandle,
+    action: String,
+    accelerator: String,
+) -> Result<(), String> {
+    app.state::<HotkeyRegistry>().register(&app, &action, &accelerator)
+}
+
+// 当前所有 action -> 快捷键组合 的绑定
+#[tauri::command]
+pub fn get_hotkeys(app: tauri::AppHandle) -> Result<HashMap<String, Vec<String>>, String> {
+    Ok(app.state::<HotkeyRegistry>().get_all())
+}
+
+// 解绑某个 action 上的所有快捷键
+#[tauri::command]
+pub fn unregister_hotkey(app: tauri::AppHandle, action: String) -> Result<(), String> {
+    app.state::<HotkeyRegistry>().unregister(&app, &action)
+}
+
+// 读取当前运行时的 AI provider 配置（持久化存储，不是进程环境变量）
+#[tauri::command]
+pub fn get_ai_settings(app: tauri::AppHandle) -> Result<AiSettings, String> {
+    Ok(app.state::<AiSettingsStore>().get())
+}
+
+// 覆盖运行时的 AI provider 配置；ai_commands::chat_stream 下次请求就会用上，不需要重启
+#[tauri::command]
+pub fn set_ai_settings(app: tauri::AppHandle, settings: AiSettings) -> Result<(), String> {
+    app.state::<AiSettingsStore>().set(settings);
+    Ok(())
+}
@@ -0,0 +1,504 @@
+// 桌面端专属的启动逻辑：生产模式下拉起打包的 Next.js server、文件对话框、
+// 桌面风格的更新检查/安装命令。这些依赖真实文件系统/子进程；lib.rs 的 run()
+// 不加 #[cfg(desktop)] 限制，在所有目标上都挂载这个模块和它的命令。
+use crate::file_scope::PathScope;
+use serde::Serialize;
+use std::process::Child;
+use std::sync::Mutex;
+use tauri::Manager;
+use tauri_plugin_dialog::DialogExt;
+use tauri_plugin_updater::UpdaterExt;
+#[cfg(not(debug_assertions))]
+use std::net::{SocketAddr, TcpListener, TcpStream};
+#[cfg(not(debug_assertions))]
+use std::path::PathBuf;
+#[cfg(not(debug_assertions))]
+use std::process::Command;
+#[cfg(not(debug_assertions))]
+use std::time::{Duration, Instant};
+
+// Global variable to store the Next.js server process
+static SERVER_PROCESS: Mutex<Option<Child>> = Mutex::new(None);
+
+// 服务起不来就重启几次，而不是无限重启把机器拖死
+#[cfg(not(debug_assertions))]
+const MAX_SERVER_RESTARTS: u32 = 3;
+#[cfg(not(debug_assertions))]
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+// run() 的 setup 钩子调用这个函数，负责 PathScope 管理 + 生产模式
+// 下拉起打包的 Next.js server
+pub fn setup(app: &tauri::AppHandle) -> Result<(), Box<dyn std::error::Error>> {
+    app.manage(PathScope::new(app));
+
+    #[cfg(not(debug_assertions))]
+    {
+        println!("Production mode detected - starting Next.js server");
+        match start_nextjs_server(app.clone()) {
+            Ok(_) => println!("Next.js server started successfully"),
+            Err(e) => {
+                eprintln!("FATAL ERROR starting Next.js server: {}", e);
+                eprintln!("Error details: {:?}", e);
+                return Err(e);
+            }
+        }
+    }
+    #[cfg(debug_assertions)]
+    {
+        println!("Debug mode - skipping Next.js server start");
+    }
+
+    Ok(())
+}
+
+// 主窗口关闭时调用，停掉生产模式下拉起的 Next.js server
+pub fn stop_nextjs_server() {
+    if let Ok(mut process) = SERVER_PROCESS.lock() {
+        if let Some(mut child) = process.take() {
+            let _ = child.kill();
+            println!("Next.js server stopped");
+        }
+    }
+}
+
+#[cfg(not(debug_assertions))]
+fn start_nextjs_server(app_handle: tauri::AppHandle) -> Result<(), Box<dyn std::error::Error>> {
+    use std::env;
+    use std::fs;
+
+    println!("[1/7] Getting resource directory path...");
+
+    // Try to get resource directory from Tauri (works when bundled)
+    let server_path = if let Ok(resource_dir) = app_handle.path().resource_dir() {
+        println!("  ✓ Resource directory from Tauri: {:?}", resource_dir);
+        let tauri_server_path = resource_dir.join("out");
+        if tauri_server_path.exists() {
+            println!("  ✓ Using Tauri bundled resources");
+            tauri_server_path
+        } else {
+            println!("  ⚠ Tauri resource dir doesn't contain 'out', trying portable mode...");
+            // Fall back to portable mode
+            let exe_dir = env::current_exe()?.parent()
+                .ok_or("Failed to get exe directory")?
+                .to_path_buf();
+            println!("  Executable directory: {:?}", exe_dir);
+            let portable_path = exe_dir.join("out");
+            if portable_path.exists() {
+                println!("  ✓ Using portable mode resources");
+                portable_path
+            } else {
+                // Try one more location: next to the project root
+                let workspace_path = exe_dir.parent()
+                    .and_then(|p| p.parent())
+                    .ok_or("Failed to find workspace")?
+                    .join("out");
+                println!("  Trying workspace path: {:?}", workspace_path);
+                if workspace_path.exists() {
+                    println!("  ✓ Using workspace resources");
+                    workspace_path
+                } else {
+                    eprintln!("  ✗ Could not find 'out' directory in any location!");
+                    eprintln!("  Searched: {:?}, {:?}, {:?}", tauri_server_path, portable_path, workspace_path);
+                    return Err("'out' directory not found in any expected location".into());
+                }
+            }
+        }
+    } else {
+        println!("  ⚠ Could not get Tauri resource directory, using portable mode...");
+        // Fall back to portable mode
+        let exe_dir = env::current_exe()?.parent()
+            .ok_or("Failed to get exe directory")?
+            .to_path_buf();
+        println!("  Executable directory: {:?}", exe_dir);
+        let portable_path = exe_dir.join("out");
+        if portable_path.exists() {
+            println!("  ✓ Using portable mode resources");
+            portable_path
+        } else {
+            // Try one more location: next to the project root (for dev builds)
+            let workspace_path = exe_dir.parent()
+                .and_then(|p| p.parent())
+                .ok_or("Failed to find workspace")?
+                .join("out");
+            println!("  Trying workspace path: {:?}", workspace_path);
+            if workspace_path.exists() {
+                println!("  ✓ Using workspace resources");
+                workspace_path
+            } else {
+                eprintln!("  ✗ Could not find 'out' directory!");
+                eprintln!("  Searched: {:?}, {:?}", portable_path, workspace_path);
+                return Err("'out' directory not found".into());
+            }
+        }
+    };
+
+    println!("[2/7] Server path determined: {:?}", server_path);
+
+    println!("[3/7] Checking if server.js exists...");
+    let server_js = server_path.join("server.js");
+    if !server_js.exists() {
+        eprintln!("  ✗ server.js not found!");
+        eprintln!("  Looking for contents in server path...");
+        if let Ok(entries) = fs::read_dir(&server_path) {
+            for entry in entries {
+                if let Ok(entry) = entry {
+                    println!("    - {:?}", entry.path());
+                }
+            }
+        }
+        return Err(format!("server.js not found at: {:?}", server_js).into());
+    }
+    println!("  ✓ server.js exists");
+
+    println!("[4/7] Starting Next.js server...");
+
+    // 优先使用 portable 目录中的 node.exe（如果存在）
+    let exe_dir = env::current_exe()?.parent()
+        .ok_or("Failed to get exe directory")?
+        .to_path_buf();
+    let portable_node = exe_dir.join("node.exe");
+
+    let node_command = if portable_node.exists() {
+        println!("  ✓ 使用 portable 模式的 Node.js: {:?}", portable_node);
+        portable_node.to_string_lossy().to_string()
+    } else {
+        println!("  ⚠ 未找到 portable Node.js，使用系统 PATH 中的 node");
+        println!("  提示: 如需完全 portable，请将 node.exe 放到可执行文件目录");
+        "node".to_string()
+    };
+
+    // 6001 被占用时退回一个系统分配的临时端口，而不是硬编码死 6001
+    let port = find_available_port(6001);
+    if port != 6001 {
+        println!("  ⚠ 端口 6001 被占用，改用端口 {}", port);
+    }
+
+    println!("  Command: {} server.js", node_command);
+    println!("  Working directory: {:?}", server_path);
+    println!("  Environment: PORT={}", port);
+
+    let child = spawn_nextjs_process(&node_command, &server_path, port).map_err(|e| {
+        eprintln!("  ✗ Failed to spawn server process: {}", e);
+        if !portable_node.exists() {
+            eprintln!("  Is Node.js installed and in PATH?");
+            eprintln!("  Or place node.exe in the application directory for portable mode");
+        }
+        e
+    })?;
+    println!("  ✓ Server process spawned (PID: {:?})", child.id());
+
+    // Store the process handle
+    println!("[5/7] Storing process handle...");
+    *SERVER_PROCESS.lock().unwrap() = Some(child);
+    println!("  ✓ Process handle stored");
+
+    // 轮询端口而不是死等固定时长：指数退避直到服务就绪或超过 READY_TIMEOUT
+    println!("[6/7] Waiting for server to become ready (up to {:?})...", READY_TIMEOUT);
+    if !wait_for_server_ready(port, READY_TIMEOUT) {
+        eprintln!("  ✗ Server did not become ready within {:?}", READY_TIMEOUT);
+        return Err(format!("Next.js server on port {} did not become ready in time", port).into());
+    }
+    println!("  ✓ Server is ready");
+
+    // Get the main window and load the Next.js app
+    println!("[7/7] Loading Next.js app in window...");
+    if let Some(window) = app_handle.get_webview_window("main") {
+        println!("  Found main window, loading http://localhost:{}", port);
+        match window.eval(format!("window.location.href = 'http://localhost:{}';", port)) {
+            Ok(_) => println!("  ✓ URL loaded successfully"),
+            Err(e) => {
+                eprintln!("  ✗ Failed to load URL: {}", e);
+                return Err(Box::new(e));
+            }
+        }
+    } else {
+        eprintln!("  ✗ Main window not found!");
+        return Err("Main window not found".into());
+    }
+
+    spawn_nextjs_supervisor(app_handle, node_command, server_path, port);
+
+    println!("=== Server startup complete ===");
+    Ok(())
+}
+
+// 尝试绑定首选端口探测是否空闲；被占用就向系统要一个临时端口。这里存在探测和
+// 实际 bind 之间的竞态窗口，但对桌面应用场景足够了
+#[cfg(not(debug_assertions))]
+fn find_available_port(preferred: u16) -> u16 {
+    if TcpListener::bind(("127.0.0.1", preferred)).is_ok() {
+        return preferred;
+    }
+    TcpListener::bind(("127.0.0.1", 0))
+        .and_then(|listener| listener.local_addr().map(|addr| addr.port()))
+        .unwrap_or(preferred)
+}
+
+#[cfg(not(debug_assertions))]
+fn spawn_nextjs_process(
+    node_command: &str,
+    server_path: &PathBuf,
+    port: u16,
+) -> std::io::Result<Child> {
+    Command::new(node_command)
+        .arg("server.js")
+        .current_dir(server_path)
+        .env("PORT", port.to_string())
+        .spawn()
+}
+
+// 指数退避轮询端口是否已经在监听，而不是硬编码睡眠固定时长
+#[cfg(not(debug_assertions))]
+fn wait_for_server_ready(port: u16, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    let mut backoff = Duration::from_millis(100);
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    while Instant::now() < deadline {
+        if TcpStream::connect_timeout(&addr, Duration::from_millis(300)).is_ok() {
+            return true;
+        }
+        std::thread::sleep(backoff);
+        backoff = (backoff * 2).min(Duration::from_secs(2));
+    }
+    false
+}
+
+// 后台监督线程：定期检查子进程是否意外退出，在重试次数上限内拉起新进程，
+// 并通过 Tauri 事件把状态告诉前端
+#[cfg(not(debug_assertions))]
+fn spawn_nextjs_supervisor(
+    app_handle: tauri::AppHandle,
+    node_command: String,
+    server_path: PathBuf,
+    port: u16,
+) {
+    use tauri::Emitter;
+
+    std::thread::spawn(move || {
+        let mut restarts = 0u32;
+
+        loop {
+            std::thread::sleep(Duration::from_secs(2));
+
+            let exited = {
+                let mut guard = SERVER_PROCESS.lock().unwrap();
+                match guard.as_mut() {
+                    Some(child) => matches!(child.try_wait(), Ok(Some(_))),
+                    // 进程句柄被清空（比如正常关闭流程），监督线程也该退出了
+                    None => return,
+                }
+            };
+
+            if !exited {
+                continue;
+            }
+
+            if restarts >= MAX_SERVER_RESTARTS {
+                eprintln!("Next.js server crashed and exceeded {} restart attempts", MAX_SERVER_RESTARTS);
+                let _ = app_handle.emit("nextjs-server-crashed", "max restarts exceeded");
+                return;
+            }
+
+            restarts += 1;
+            eprintln!("Next.js server exited unexpectedly, restarting (attempt {}/{})", restarts, MAX_SERVER_RESTARTS);
+            let _ = app_handle.emit("nextjs-server-restarting", restarts);
+
+            match spawn_nextjs_process(&node_command, &server_path, port) {
+                Ok(child) => {
+                    *SERVER_PROCESS.lock().unwrap() = Some(child);
+                    if !wait_for_server_ready(port, READY_TIMEOUT) {
+                        let _ = app_handle.emit("nextjs-server-crashed", "restarted server did not become ready");
+                        return;
+                    }
+                }
+                Err(e) => {
+                    let _ = app_handle.emit("nextjs-server-crashed", format!("restart failed: {}", e));
+                    return;
+                }
+            }
+        }
+    });
+}
+
+// 打开文件对话框的函数
+#[tauri::command]
+pub async fn open_file_dialog(app: tauri::AppHandle) -> Result<Option<String>, String> {
+    let file_path = app.dialog()
+        .file()
+        .add_filter("Diagram Files", &["xml", "drawio"])
+        .add_filter("All Files", &["*"])
+        .blocking_pick_file();
+
+    match file_path {
+        Some(path) => match path.as_path() {
+            Some(p) => {
+                // 用户通过选择器挑中的目录视为已授权，后续 read_file 才能读到它
+                app.state::<PathScope>().grant(p);
+                Ok(Some(p.to_string_lossy().to_string()))
+            }
+            None => Ok(None),
+        },
+        None => Ok(None),
+    }
+}
+
+// 保存文件对话框的函数
+#[tauri::command]
+pub async fn save_file_dialog(app: tauri::AppHandle) -> Result<Option<String>, String> {
+    let file_path = app.dialog()
+        .file()
+        .add_filter("Diagram Files", &["xml", "drawio"])
+        .set_file_name("diagram.xml")
+        .blocking_save_file();
+
+    match file_path {
+        Some(path) => match path.as_path() {
+            Some(p) => {
+                app.state::<PathScope>().grant(p);
+                Ok(Some(p.to_string_lossy().to_string()))
+            }
+            None => Ok(None),
+        },
+        None => Ok(None),
+    }
+}
+
+// 读取文件内容的函数
+#[tauri::command]
+pub fn read_file(app: tauri::AppHandle, file_path: String) -> Result<String, String> {
+    use std::fs;
+    use std::path::Path;
+
+    let path = Path::new(&file_path);
+    let scoped_path = app.state::<PathScope>().check(path)?;
+
+    // 检查文件是否存在
+    if !scoped_path.exists() {
+        return Err("File does not exist".to_string());
+    }
+
+    // 检查是否为文件
+    if !scoped_path.is_file() {
+        return Err("Path is not a file".to_string());
+    }
+
+    // 读取文件内容
+    match fs::read_to_string(&scoped_path) {
+        Ok(content) => Ok(content),
+        Err(e) => Err(format!("Failed to read file: {}", e))
+    }
+}
+
+// 写入文件内容的函数
+#[tauri::command]
+pub fn write_file(app: tauri::AppHandle, file_path: String, content: String) -> Result<(), String> {
+    use std::fs;
+    use std::path::Path;
+
+    let path = Path::new(&file_path);
+    let scoped_path = app.state::<PathScope>().check(path)?;
+
+    // 创建父目录（如果不存在）
+    if let Some(parent) = scoped_path.parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            return Err(format!("Failed to create directory: {}", e));
+        }
+    }
+
+    // 写入文件内容
+    match fs::write(&scoped_path, content) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Failed to write file: {}", e))
+    }
+}
+
+// 结构化的更新错误，前端可以按类型分支处理而不是解析错误字符串
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UpdateError {
+    NoUpdateAvailable,
+    Network(String),
+    SignatureVerificationFailed(String),
+    Other(String),
+}
+
+impl std::fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoUpdateAvailable => write!(f, "No update available"),
+            Self::Network(msg) => write!(f, "Network error: {}", msg),
+            Self::SignatureVerificationFailed(msg) => write!(f, "Signature verification failed: {}", msg),
+            Self::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+// tauri_plugin_updater::Error 没有区分这几类失败，只能按错误信息粗略归类
+fn classify_updater_error(e: tauri_plugin_updater::Error) -> UpdateError {
+    let message = e.to_string();
+    let lower = message.to_lowercase();
+    if lower.contains("signature") {
+        UpdateError::SignatureVerificationFailed(message)
+    } else if lower.contains("network") || lower.contains("request") || lower.contains("connect") {
+        UpdateError::Network(message)
+    } else {
+        UpdateError::Other(message)
+    }
+}
+
+// 可供前端展示的更新信息：版本号、发布日期、更新日志
+#[derive(Debug, Clone, Serialize)]
+pub struct UpdateInfo {
+    pub version: String,
+    pub date: Option<String>,
+    pub body: Option<String>,
+}
+
+// 检查更新的命令函数
+#[tauri::command]
+pub async fn check_for_updates_command(app: tauri::AppHandle) -> Result<Option<UpdateInfo>, UpdateError> {
+    let updater = app.updater().map_err(classify_updater_error)?;
+    let update = updater.check().await.map_err(classify_updater_error)?;
+    Ok(update.map(|update| UpdateInfo {
+        version: update.version.clone(),
+        date: update.date.map(|d| d.to_string()),
+        body: update.body.clone(),
+    }))
+}
+
+// 下载并安装更新，过程中通过 update://progress / update://finished 事件通知前端，
+// 安装完成后直接重启应用让新版本生效
+#[tauri::command]
+pub async fn download_and_install_update(app: tauri::AppHandle) -> Result<(), UpdateError> {
+    use tauri::Emitter;
+
+    let updater = app.updater().map_err(classify_updater_error)?;
+    let update = updater
+        .check()
+        .await
+        .map_err(classify_updater_error)?
+        .ok_or(UpdateError::NoUpdateAvailable)?;
+
+    let mut downloaded: u64 = 0;
+    let progress_app = app.clone();
+    let finished_app = app.clone();
+
+    update
+        .download_and_install(
+            move |chunk_len, total| {
+                downloaded += chunk_len as u64;
+                let _ = progress_app.emit(
+                    "update://progress",
+                    serde_json::json!({ "downloaded": downloaded, "total": total }),
+                );
+            },
+            move || {
+                let _ = finished_app.emit("update://finished", ());
+            },
+        )
+        .await
+        .map_err(classify_updater_error)?;
+
+    app.restart();
+}
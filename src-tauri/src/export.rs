@@ -0,0 +1,171 @@
+// 用隐藏的零尺寸 WebView 窗口做无头渲染：加载内部渲染页面、把图表 XML 注入
+// 进去、等它把渲染结果（base64 编码的图片/PDF）通过事件带回来，再销毁窗口。
+// 这样可以在没有可见编辑器窗口的情况下拿到导出产物（预览图、导出文件等）。
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::Mutex;
+use tauri::{Listener, Manager, WebviewUrl, WebviewWindowBuilder};
+use tokio::sync::oneshot;
+use tokio::time::{timeout, Duration};
+
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// 同一个 label（这里按 format 区分）同一时间只允许跑一个导出，避免多个隐藏窗口
+// 互相抢占、或者同一格式的导出互相覆盖结果
+pub struct ExportGuard {
+    in_flight: Mutex<HashSet<String>>,
+}
+
+impl ExportGuard {
+    pub fn new() -> Self {
+        Self {
+            in_flight: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn acquire(&self, label: &str) -> Result<(), String> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if !in_flight.insert(label.to_string()) {
+            return Err(format!("An export is already running for '{}'", label));
+        }
+        Ok(())
+    }
+
+    fn release(&self, label: &str) {
+        self.in_flight.lock().unwrap().remove(label);
+    }
+}
+
+impl Default for ExportGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportCompletePayload {
+    success: bool,
+    #[serde(default)]
+    data_base64: String,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+#[tauri::command]
+pub async fn export_diagram(
+    app: tauri::AppHandle,
+    xml: String,
+    format: String,
+) -> Result<Vec<u8>, String> {
+    if !matches!(format.as_str(), "png" | "svg" | "pdf") {
+        return Err(format!("Unsupported export format: {}", format));
+    }
+
+    let label = format!("diagram-export-{}", format);
+    app.state::<ExportGuard>().acquire(&label)?;
+
+    let result = run_export(&app, &label, &xml, &format).await;
+
+    app.state::<ExportGuard>().release(&label);
+    result
+}
+
+async fn run_export(
+    app: &tauri::AppHandle,
+    label: &str,
+    xml: &str,
+    format: &str,
+) -> Result<Vec<u8>, String> {
+    // 0x0 + 不可见：只借用 WebView 做渲染，不在屏幕上留下任何痕迹
+    let window = WebviewWindowBuilder::new(app, label, WebviewUrl::App("export-renderer.html".into()))
+        .inner_size(0.0, 0.0)
+        .visible(false)
+        .build()
+        .map_err(|e| format!("Failed to create export window: {}", e))?;
+
+    let (tx, rx) = oneshot::channel::<Result<Vec<u8>, String>>();
+    let tx = Mutex::new(Some(tx));
+
+    // export-renderer.html 渲染完成后通过这个事件把 base64 数据带回来
+    let unlisten_id = window.listen("export-complete", move |event| {
+        let result = match serde_json::from_str::<ExportCompletePayload>(event.payload()) {
+            Ok(payload) if payload.success => decode_base64(&payload.data_base64)
+                .ok_or_else(|| "Export produced invalid base64 data".to_string()),
+            Ok(payload) => Err(payload.error.unwrap_or_else(|| "Export failed".to_string())),
+            Err(e) => Err(format!("Invalid export event payload: {}", e)),
+        };
+        if let Some(tx) = tx.lock().unwrap().take() {
+            let _ = tx.send(result);
+        }
+    });
+
+    let script = format!(
+        "window.renderDiagramForExport({}, {});",
+        serde_json::to_string(xml).unwrap_or_default(),
+        serde_json::to_string(format).unwrap_or_default()
+    );
+
+    if let Err(e) = window.eval(&script) {
+        window.unlisten(unlisten_id);
+        let _ = window.close();
+        return Err(format!("Failed to start render: {}", e));
+    }
+
+    // 渲染是异步的；用带超时的 await 等待完成事件，不阻塞 Tokio worker 线程
+    let result = match timeout(EXPORT_TIMEOUT, rx).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(_)) => Err("Export window closed before completing".to_string()),
+        Err(_) => Err("Timed out waiting for diagram render".to_string()),
+    };
+
+    window.unlisten(unlisten_id);
+    let _ = window.close();
+
+    result
+}
+
+// 标准 base64（RFC 4648）解码，不引入额外依赖
+fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut reverse = [255u8; 256];
+    for (i, &b) in TABLE.iter().enumerate() {
+        reverse[b as usize] = i as u8;
+    }
+
+    let mut out = Vec::with_capacity(input.len() / 4 * 3);
+    let mut chunk = [0u8; 4];
+    let mut chunk_len = 0;
+
+    for byte in input.bytes() {
+        if byte == b'\n' || byte == b'\r' {
+            continue;
+        }
+        if byte == b'=' {
+            break;
+        }
+        let value = reverse[byte as usize];
+        if value == 255 {
+            return None;
+        }
+        chunk[chunk_len] = value;
+        chunk_len += 1;
+        if chunk_len == 4 {
+            out.push((chunk[0] << 2) | (chunk[1] >> 4));
+            out.push((chunk[1] << 4) | (chunk[2] >> 2));
+            out.push((chunk[2] << 6) | chunk[3]);
+            chunk_len = 0;
+        }
+    }
+
+    match chunk_len {
+        0 => {}
+        2 => out.push((chunk[0] << 2) | (chunk[1] >> 4)),
+        3 => {
+            out.push((chunk[0] << 2) | (chunk[1] >> 4));
+            out.push((chunk[1] << 4) | (chunk[2] >> 2));
+        }
+        _ => return None,
+    }
+
+    Some(out)
+}
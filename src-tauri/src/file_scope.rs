@@ -0,0 +1,79 @@
+// 给 read_file/write_file 这类任意路径命令加一层目录白名单，参照 Tauri 的
+// ACL/capability 模型：只允许访问配置好的根目录，而不是整个文件系统。
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::Manager;
+
+pub struct PathScope {
+    allowed_roots: Mutex<Vec<PathBuf>>,
+}
+
+impl PathScope {
+    // 初始根目录来自 ALLOWED_PATHS 环境变量（逗号分隔）以及应用的数据目录；
+    // 之后 open_file_dialog/save_file_dialog 选中的目录会被动态加进来
+    pub fn new(app: &tauri::AppHandle) -> Self {
+        let mut roots = Vec::new();
+
+        if let Ok(allowed) = std::env::var("ALLOWED_PATHS") {
+            for raw in allowed.split(',') {
+                let raw = raw.trim();
+                if raw.is_empty() {
+                    continue;
+                }
+                if let Ok(canon) = std::fs::canonicalize(raw) {
+                    roots.push(canon);
+                }
+            }
+        }
+
+        if let Ok(data_dir) = app.path().app_data_dir() {
+            let _ = std::fs::create_dir_all(&data_dir);
+            if let Ok(canon) = std::fs::canonicalize(&data_dir) {
+                roots.push(canon);
+            }
+        }
+
+        Self {
+            allowed_roots: Mutex::new(roots),
+        }
+    }
+
+    // 把用户通过文件选择器选中的路径所在目录纳入授权集合，这样选择器挑的文件
+    // 后续的 read_file/write_file 调用才不会被挡
+    pub fn grant(&self, path: &Path) {
+        let Some(parent) = path.parent() else { return };
+        let Ok(canon) = std::fs::canonicalize(parent) else { return };
+        let mut roots = self.allowed_roots.lock().unwrap();
+        if !roots.contains(&canon) {
+            roots.push(canon);
+        }
+    }
+
+    // 校验 path 是否落在任一授权根目录之内；canonicalize 一并处理了 `..` 穿越
+    // 和符号链接指向授权目录之外的情况
+    pub fn check(&self, path: &Path) -> Result<PathBuf, String> {
+        let canon = canonicalize_best_effort(path)?;
+        let roots = self.allowed_roots.lock().unwrap();
+        if roots.iter().any(|root| canon.starts_with(root)) {
+            Ok(canon)
+        } else {
+            Err(format!(
+                "Access denied: '{}' is outside the allowed path scope",
+                path.display()
+            ))
+        }
+    }
+}
+
+// write_file 的目标文件可能还不存在，这时对父目录做 canonicalize 再拼回文件名
+fn canonicalize_best_effort(path: &Path) -> Result<PathBuf, String> {
+    if let Ok(canon) = std::fs::canonicalize(path) {
+        return Ok(canon);
+    }
+
+    let parent = path.parent().ok_or_else(|| "Invalid path".to_string())?;
+    let file_name = path.file_name().ok_or_else(|| "Invalid path".to_string())?;
+    let canon_parent =
+        std::fs::canonicalize(parent).map_err(|e| format!("Invalid path: {}", e))?;
+    Ok(canon_parent.join(file_name))
+}
@@ -0,0 +1,121 @@
+// 管理用户自定义的全局快捷键绑定：持久化到应用数据目录，并通过
+// tauri-plugin-global-shortcut 实际注册/注销系统级快捷键
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tauri::Manager;
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut};
+
+pub struct HotkeyRegistry {
+    // action -> 绑定的快捷键组合（同一个 action 允许绑定多个），用原始用户输入
+    // 的形式持久化/展示给前端
+    bindings: Mutex<HashMap<String, Vec<String>>>,
+    // Shortcut 的规范化字符串（如 "super+KeyK"）-> action。触发回调里
+    // `shortcut.to_string()` 给出的就是这个规范化形式，跟用户输入的原始
+    // accelerator 文本（如 "CmdOrControl+K"）不是同一种写法，不能直接比较
+    lookup: Mutex<HashMap<String, String>>,
+    config_path: PathBuf,
+}
+
+impl HotkeyRegistry {
+    pub fn new(app: &tauri::AppHandle) -> Self {
+        let config_path = app
+            .path()
+            .app_data_dir()
+            .map(|dir| dir.join("hotkeys.json"))
+            .unwrap_or_else(|_| PathBuf::from("hotkeys.json"));
+
+        let bindings: HashMap<String, Vec<String>> = std::fs::read_to_string(&config_path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+
+        let mut lookup = HashMap::new();
+
+        // 重启后把上次保存的绑定重新注册到系统，不然重启前注册的快捷键不会自动恢复
+        for (action, accelerators) in &bindings {
+            for accelerator in accelerators {
+                if let Ok(shortcut) = accelerator.parse::<Shortcut>() {
+                    let _ = app.global_shortcut().register(shortcut.clone());
+                    lookup.insert(shortcut.to_string(), action.clone());
+                }
+            }
+        }
+
+        Self {
+            bindings: Mutex::new(bindings),
+            lookup: Mutex::new(lookup),
+            config_path,
+        }
+    }
+
+    fn save(&self) {
+        if let Some(parent) = self.config_path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        if let Ok(bindings) = self.bindings.lock() {
+            if let Ok(json) = serde_json::to_string_pretty(&*bindings) {
+                let _ = std::fs::write(&self.config_path, json);
+            }
+        }
+    }
+
+    pub fn get_all(&self) -> HashMap<String, Vec<String>> {
+        self.bindings.lock().unwrap().clone()
+    }
+
+    pub fn register(
+        &self,
+        app: &tauri::AppHandle,
+        action: &str,
+        accelerator: &str,
+    ) -> Result<(), String> {
+        let shortcut: Shortcut = accelerator
+            .parse()
+            .map_err(|e| format!("Invalid accelerator '{}': {}", accelerator, e))?;
+
+        app.global_shortcut()
+            .register(shortcut.clone())
+            .map_err(|e| format!("Failed to register hotkey '{}': {}", accelerator, e))?;
+
+        let mut bindings = self.bindings.lock().unwrap();
+        bindings
+            .entry(action.to_string())
+            .or_default()
+            .push(accelerator.to_string());
+        drop(bindings);
+
+        self.lookup
+            .lock()
+            .unwrap()
+            .insert(shortcut.to_string(), action.to_string());
+
+        self.save();
+        Ok(())
+    }
+
+    pub fn unregister(&self, app: &tauri::AppHandle, action: &str) -> Result<(), String> {
+        let accelerators = {
+            let mut bindings = self.bindings.lock().unwrap();
+            bindings.remove(action).unwrap_or_default()
+        };
+
+        let mut lookup = self.lookup.lock().unwrap();
+        for accelerator in &accelerators {
+            if let Ok(shortcut) = accelerator.parse::<Shortcut>() {
+                let _ = app.global_shortcut().unregister(shortcut.clone());
+                lookup.remove(&shortcut.to_string());
+            }
+        }
+        drop(lookup);
+
+        self.save();
+        Ok(())
+    }
+
+    // 全局快捷键触发的回调里只拿得到规范化之后的 accelerator 字符串
+    // （`shortcut.to_string()`），要按同样规范化之后的形式去反查 action
+    pub fn action_for_shortcut(&self, normalized_accelerator: &str) -> Option<String> {
+        self.lookup.lock().unwrap().get(normalized_accelerator).cloned()
+    }
+}
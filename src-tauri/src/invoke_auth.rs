@@ -0,0 +1,91 @@
+// 给通过 access code 校验的会话签发一个 per-session 的 invoke token，存进
+// managed state，按前端生成的 session_id 分开记录。像 chat_stream 这种会
+// 直接消耗 AI provider 额度的命令，在真正发起请求前都要带上自己那份 session
+// 对应的 token 并校验通过——这样即使 WebView 加载到了远程/第三方内容，也没法
+// 绕过 verify_access_code 直接调用流式接口；多个会话并发登录也不会互相挤掉
+// 对方的 token（单一全局 token 会有这个问题）。
+// 部署没有配置任何 access code（ACCESS_CODE_LIST 未设置或列表为空）时不强制
+// 要求 token——这时 verify_access_code 直接放行且不签发 token，和原来无门槛的
+// 行为保持一致。
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct InvokeAuthGate {
+    // session_id -> 签发给这个会话的 token
+    tokens: Mutex<HashMap<String, String>>,
+}
+
+impl InvokeAuthGate {
+    pub fn new() -> Self {
+        Self {
+            tokens: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn issue(&self, session_id: &str) -> String {
+        let token = generate_token();
+        self.tokens
+            .lock()
+            .unwrap()
+            .insert(session_id.to_string(), token.clone());
+        token
+    }
+
+    pub fn verify(&self, session_id: &str, presented: Option<&str>) -> Result<(), InvokeAuthError> {
+        let access_codes_configured = std::env::var("ACCESS_CODE_LIST")
+            .map(|list| list.split(',').any(|code| !code.trim().is_empty()))
+            .unwrap_or(false);
+        if !access_codes_configured {
+            return Ok(());
+        }
+
+        let tokens = self.tokens.lock().unwrap();
+        match (tokens.get(session_id).map(String::as_str), presented) {
+            (Some(expected), Some(presented)) if expected == presented => Ok(()),
+            (_, None) => {
+                log::warn!("AI stream command invoked without an invoke token (session {})", session_id);
+                Err(InvokeAuthError::MissingToken)
+            }
+            _ => {
+                log::warn!("AI stream command invoked with an invalid invoke token (session {})", session_id);
+                Err(InvokeAuthError::InvalidToken)
+            }
+        }
+    }
+}
+
+impl Default for InvokeAuthGate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 不引入额外依赖，用单调计数器 + 当前时间拼出一个同进程内唯一的 token
+fn generate_token() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let counter = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{:x}-{:x}-{:x}", nanos, std::process::id(), counter)
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InvokeAuthError {
+    MissingToken,
+    InvalidToken,
+}
+
+impl std::fmt::Display for InvokeAuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvokeAuthError::MissingToken => write!(f, "Missing invoke token"),
+            InvokeAuthError::InvalidToken => write!(f, "Invalid invoke token"),
+        }
+    }
+}
@@ -1,8 +1,15 @@
-
+use tauri::{Emitter, Manager};
 
 mod commands;
 mod ai_chat;
 mod ai_commands;
+mod rate_limit;
+mod hotkeys;
+mod export;
+mod ai_settings;
+mod invoke_auth;
+mod file_scope;
+mod desktop;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
@@ -31,19 +38,66 @@ pub fn run() {
   let builder = tauri::Builder::default()
     .plugin(tauri_plugin_log::Builder::default().build())
     .plugin(tauri_plugin_shell::init())
+    .plugin(tauri_plugin_dialog::init())
+    .plugin(tauri_plugin_updater::Builder::new().build())
+    .plugin(
+      tauri_plugin_global_shortcut::Builder::new()
+        .with_handler(|app, shortcut, event| {
+          // 只在按下时触发一次，忽略松开事件，避免每次按键发两次事件
+          if event.state() == tauri_plugin_global_shortcut::ShortcutState::Pressed {
+            let registry = app.state::<hotkeys::HotkeyRegistry>();
+            if let Some(action) = registry.action_for_shortcut(&shortcut.to_string()) {
+              let _ = app.emit("hotkey-triggered", action);
+            }
+          }
+        })
+        .build(),
+    )
+    .manage(rate_limit::RateLimiter::new())
+    .manage(export::ExportGuard::new())
+    .manage(invoke_auth::InvokeAuthGate::new())
     .invoke_handler(tauri::generate_handler![
       commands::get_config,
       commands::verify_access_code,
+      commands::check_for_update,
+      commands::install_update,
+      commands::register_hotkey,
+      commands::get_hotkeys,
+      commands::unregister_hotkey,
+      commands::get_ai_settings,
+      commands::set_ai_settings,
+      export::export_diagram,
       ai_commands::chat_stream,
+      ai_commands::chat_stream_genai,
+      desktop::open_file_dialog,
+      desktop::save_file_dialog,
+      desktop::read_file,
+      desktop::write_file,
+      desktop::check_for_updates_command,
+      desktop::download_and_install_update,
     ])
-    .setup(|_app| {
+    .setup(|app| {
       #[cfg(debug_assertions)]
       {
         println!("[Development] Running in development mode");
         println!("[Development] Expecting Next.js dev server on port 6002");
       }
 
+      let handle = app.handle().clone();
+      app.manage(hotkeys::HotkeyRegistry::new(&handle));
+      app.manage(ai_settings::AiSettingsStore::new(&handle));
+      desktop::setup(&handle)?;
+
       Ok(())
+    })
+    .on_window_event(|window, event| {
+      // 只有主窗口关闭才停 server；导出用的隐藏窗口关闭时不能连带停掉它
+      if let tauri::WindowEvent::Destroyed = event {
+        if window.label() != "main" {
+          return;
+        }
+        desktop::stop_nextjs_server();
+      }
     });
 
   builder
@@ -0,0 +1,150 @@
+// 落实 get_config 已经宣传出去的 daily_request_limit / daily_token_limit / tpm_limit：
+// 按 access code（拿不到就退回 session_id）维护每个调用方的用量。
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+const SECS_PER_DAY: u64 = 86400;
+
+struct Usage {
+    day_index: u64,
+    daily_requests: u32,
+    daily_tokens: u32,
+    // TPM 的令牌桶：每次检查时先按 tpm_limit/60 的速率补充，上限为 tpm_limit
+    tokens_remaining: f64,
+    last_refill: Instant,
+}
+
+impl Usage {
+    fn new(tpm_limit: u32) -> Self {
+        Self {
+            day_index: current_day_index(),
+            daily_requests: 0,
+            daily_tokens: 0,
+            tokens_remaining: tpm_limit as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn roll_over_if_new_day(&mut self) {
+        let today = current_day_index();
+        if today != self.day_index {
+            self.day_index = today;
+            self.daily_requests = 0;
+            self.daily_tokens = 0;
+        }
+    }
+
+    fn refill(&mut self, tpm_limit: u32) {
+        if tpm_limit == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens_remaining =
+            (self.tokens_remaining + elapsed * (tpm_limit as f64 / 60.0)).min(tpm_limit as f64);
+        self.last_refill = now;
+    }
+}
+
+fn current_day_index() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / SECS_PER_DAY
+}
+
+fn secs_until_next_day() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    SECS_PER_DAY - (now % SECS_PER_DAY)
+}
+
+// 哪个限额被打到了，以及建议等待多久再试
+#[derive(Debug, Clone)]
+pub struct LimitExceeded {
+    pub limit: &'static str,
+    pub retry_after_secs: u64,
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} exceeded, retry after {}s",
+            self.limit, self.retry_after_secs
+        )
+    }
+}
+
+pub struct RateLimiter {
+    usage: Mutex<HashMap<String, Usage>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self {
+            usage: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 请求发出前调用；三个 limit 都是 0 表示不限。通过则占用一次 daily_requests 名额
+    pub fn check(
+        &self,
+        key: &str,
+        daily_request_limit: u32,
+        daily_token_limit: u32,
+        tpm_limit: u32,
+    ) -> Result<(), LimitExceeded> {
+        let mut table = self.usage.lock().unwrap();
+        let entry = table
+            .entry(key.to_string())
+            .or_insert_with(|| Usage::new(tpm_limit));
+        entry.roll_over_if_new_day();
+        entry.refill(tpm_limit);
+
+        if daily_request_limit != 0 && entry.daily_requests >= daily_request_limit {
+            return Err(LimitExceeded {
+                limit: "daily_request_limit",
+                retry_after_secs: secs_until_next_day(),
+            });
+        }
+        if daily_token_limit != 0 && entry.daily_tokens >= daily_token_limit {
+            return Err(LimitExceeded {
+                limit: "daily_token_limit",
+                retry_after_secs: secs_until_next_day(),
+            });
+        }
+        if tpm_limit != 0 && entry.tokens_remaining < 1.0 {
+            let secs = ((1.0 - entry.tokens_remaining) / (tpm_limit as f64 / 60.0)).ceil() as u64;
+            return Err(LimitExceeded {
+                limit: "tpm_limit",
+                retry_after_secs: secs,
+            });
+        }
+
+        entry.daily_requests += 1;
+        Ok(())
+    }
+
+    // Finish 事件到达后调用，把 provider 返回的实际 usage 记到账上
+    pub fn debit(&self, key: &str, tokens: u32, tpm_limit: u32) {
+        let mut table = self.usage.lock().unwrap();
+        if let Some(entry) = table.get_mut(key) {
+            entry.roll_over_if_new_day();
+            entry.daily_tokens += tokens;
+            if tpm_limit != 0 {
+                entry.tokens_remaining = (entry.tokens_remaining - tokens as f64).max(0.0);
+            }
+        }
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}